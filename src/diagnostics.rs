@@ -0,0 +1,129 @@
+use error::AsmError;
+
+/// Collects the errors found while assembling a file, so that one run
+/// can report every bad instruction instead of stopping at the first.
+///
+/// At most `max_errors` errors are kept; any further errors are only counted.
+/// Warnings, about code that assembles but probably isn't what was meant,
+/// are all kept and don't count as errors.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    errors: Vec<AsmError>,
+    warnings: Vec<AsmError>,
+    max_errors: Option<usize>,
+    suppressed: usize,
+}
+
+impl Diagnostics {
+    /// Initializes a collector that keeps every error
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    /// Initializes a collector that keeps at most `max_errors` errors
+    pub fn with_limit(max_errors: usize) -> Diagnostics {
+        Diagnostics {
+            max_errors: Some(max_errors),
+            ..Diagnostics::default()
+        }
+    }
+
+    /// Records an error, or just counts it if the limit has been reached
+    pub fn push(&mut self, error: AsmError) {
+        if self.is_full() {
+            self.suppressed += 1;
+        } else {
+            self.errors.push(error);
+        }
+    }
+
+    /// Records a warning
+    pub fn warn(&mut self, warning: AsmError) {
+        self.warnings.push(warning);
+    }
+
+    /// The warnings, in the order they were pushed
+    pub fn warnings(&self) -> &[AsmError] {
+        &self.warnings
+    }
+
+    /// Whether the error limit has been reached
+    pub fn is_full(&self) -> bool {
+        self.max_errors.is_some_and(|max| self.errors.len() >= max)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count() > 0
+    }
+
+    /// The total number of errors pushed, including ones over the limit
+    pub fn count(&self) -> usize {
+        self.errors.len() + self.suppressed
+    }
+
+    /// The errors that were kept, in the order they were pushed
+    pub fn errors(&self) -> &[AsmError] {
+        &self.errors
+    }
+
+    pub fn into_errors(self) -> Vec<AsmError> {
+        self.errors
+    }
+
+    /// Returns a summary line such as "3 errors found"
+    pub fn summary(&self) -> String {
+        let count = self.count();
+        let mut summary = format!("{} error{} found", count, if count == 1 { "" } else { "s" });
+        if self.suppressed > 0 {
+            summary.push_str(&format!(" ({} not shown)", self.suppressed));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Span;
+
+    fn error(line: usize) -> AsmError {
+        AsmError::UnknownComp {
+            comp: "D+X".to_string(),
+            span: Span::new("test.asm", line, 0, 3),
+        }
+    }
+
+    #[test]
+    fn collects_every_error() {
+        let mut diagnostics = Diagnostics::new();
+        assert!(!diagnostics.has_errors());
+        diagnostics.push(error(1));
+        diagnostics.push(error(2));
+        assert_eq!(diagnostics.count(), 2);
+        assert_eq!(diagnostics.errors()[1].span().line, 2);
+        assert_eq!(diagnostics.summary(), "2 errors found");
+    }
+
+    #[test]
+    fn warnings_are_not_errors() {
+        let mut diagnostics = Diagnostics::with_limit(1);
+        diagnostics.push(error(1));
+        diagnostics.warn(error(2));
+        diagnostics.warn(error(3));
+        assert_eq!(diagnostics.count(), 1);
+        assert_eq!(diagnostics.warnings().len(), 2);
+        assert_eq!(diagnostics.summary(), "1 error found");
+    }
+
+    #[test]
+    fn limits_kept_errors() {
+        let mut diagnostics = Diagnostics::with_limit(2);
+        for line in 1..5 {
+            diagnostics.push(error(line));
+        }
+        assert!(diagnostics.is_full());
+        assert_eq!(diagnostics.errors().len(), 2);
+        assert_eq!(diagnostics.count(), 4);
+        assert_eq!(diagnostics.summary(), "4 errors found (2 not shown)");
+    }
+}
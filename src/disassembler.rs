@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use instruction::{Comp, Dest, Instruction, Jump, Value};
+use symbol_map::SymbolMap;
+use CDecoder;
+
+/// A disassembled machine word
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Word {
+    /// A word that decodes to an instruction
+    Instruction(Instruction),
+    /// A word that isn't a valid instruction, such as data stored in ROM
+    Data(u16),
+}
+
+impl fmt::Display for Word {
+    /// Formats the word as a line of assembly. Data is written as a comment,
+    /// since Hack assembly has no way to express it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Word::Instruction(ref instruction) => write!(f, "{}", instruction),
+            Word::Data(word) => write!(f, "// data: {:016b}", word),
+        }
+    }
+}
+
+/// Turns machine words back into instructions, using the
+/// inverse of a CDecoder's tables
+pub struct Disassembler {
+    dest_map: HashMap<u16, Dest>,
+    comp_map: HashMap<u16, Comp>,
+    jump_map: HashMap<u16, Jump>,
+}
+
+/// Inverts a table of mnemonic encodings, skipping mnemonics the AST can't represent
+fn invert<T, F: Fn(&str) -> Option<T>>(table: &HashMap<String, u16>, from_mnemonic: F) -> HashMap<u16, T> {
+    table.iter()
+        .filter_map(|(mnemonic, bits)| from_mnemonic(mnemonic).map(|field| (*bits, field)))
+        .collect()
+}
+
+impl Disassembler {
+    pub fn new(c_decoder: &CDecoder) -> Disassembler {
+        Disassembler {
+            dest_map: invert(&c_decoder.dest_map, Dest::from_mnemonic),
+            comp_map: invert(&c_decoder.comp_map, Comp::from_mnemonic),
+            jump_map: invert(&c_decoder.jump_map, Jump::from_mnemonic),
+        }
+    }
+
+    /// Decodes a single machine word
+    pub fn decode_word(&self, word: u16) -> Word {
+        if word & 0x8000 == 0 {
+            return Word::Instruction(Instruction::A(Value::Constant(word)));
+        }
+        // 111a cccc ccdd djjj
+        if word >> 13 != 0b111 {
+            return Word::Data(word);
+        }
+        let comp = self.comp_map.get(&((word >> 6) & 0x7f));
+        let dest = match (word >> 3) & 0x7 {
+            0 => Some(None),
+            bits => self.dest_map.get(&bits).map(|dest| Some(*dest)),
+        };
+        let jump = match word & 0x7 {
+            0 => Some(None),
+            bits => self.jump_map.get(&bits).map(|jump| Some(*jump)),
+        };
+        match (dest, comp, jump) {
+            (Some(dest), Some(comp), Some(jump)) => Word::Instruction(Instruction::C { dest, comp: *comp, jump }),
+            _ => Word::Data(word),
+        }
+    }
+
+    /// Decodes a sequence of machine words
+    pub fn disassemble(&self, words: &[u16]) -> Vec<Word> {
+        words.iter().map(|word| self.decode_word(*word)).collect()
+    }
+
+    /// Decodes a sequence of machine words, putting back the names in a symbol map
+    ///
+    /// Labels are declared before the words they point to. An A instruction is
+    /// named after a label when the next instruction jumps, and after a variable
+    /// otherwise, falling back to whichever symbol exists for its address.
+    ///
+    /// Returns: the words, with a Label instruction for each label declaration
+    pub fn disassemble_with_symbols(&self, words: &[u16], symbols: &SymbolMap) -> Vec<Word> {
+        let decoded = self.disassemble(words);
+        let mut listing = Vec::with_capacity(decoded.len());
+        let declare_labels = |listing: &mut Vec<Word>, address: usize| {
+            for label in symbols.labels_at(address as u16) {
+                listing.push(Word::Instruction(Instruction::Label(label.to_string())));
+            }
+        };
+        for (address, word) in decoded.iter().enumerate() {
+            declare_labels(&mut listing, address);
+            let jumps = matches!(decoded.get(address + 1),
+                                 Some(Word::Instruction(Instruction::C { jump: Some(_), .. })));
+            let symbol = match *word {
+                Word::Instruction(Instruction::A(Value::Constant(value))) => {
+                    let label = symbols.label_at(value);
+                    let variable = symbols.variable_at(value);
+                    if jumps { label.or(variable) } else { variable.or(label) }
+                }
+                _ => None,
+            };
+            listing.push(match symbol {
+                Some(name) => Word::Instruction(Instruction::A(Value::Symbol(name.to_string()))),
+                None => word.clone(),
+            });
+        }
+        // labels such as (END) can point just past the last instruction
+        declare_labels(&mut listing, decoded.len());
+        listing
+    }
+}
+
+impl Default for Disassembler {
+    /// Initializes a Disassembler with the compiled-in Hack tables
+    fn default() -> Disassembler {
+        Disassembler::new(&CDecoder::default())
+    }
+}
+
+/// Joins disassembled words into assembly text, one line per word
+fn format_listing(words: &[Word]) -> String {
+    let mut listing = String::new();
+    for word in words.iter() {
+        listing.push_str(&word.to_string());
+        listing.push('\n');
+    }
+    listing
+}
+
+/// Disassembles machine words into Hack assembly, one line per word
+pub fn disassemble(words: &[u16]) -> String {
+    format_listing(&Disassembler::default().disassemble(words))
+}
+
+/// Disassembles machine words into Hack assembly, naming labels and
+/// variables with the symbols in a symbol map
+pub fn disassemble_with_symbols(words: &[u16], symbols: &SymbolMap) -> String {
+    format_listing(&Disassembler::default().disassemble_with_symbols(words, symbols))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {assemble, assemble_source, Diagnostics};
+
+    #[test]
+    fn decode_words() {
+        let disassembler = Disassembler::default();
+        assert_eq!(disassembler.decode_word(0x0010).to_string(), "@16");
+        assert_eq!(disassembler.decode_word(0b1110011111011000).to_string(), "MD=D+1");
+        assert_eq!(disassembler.decode_word(0b1111110111001010).to_string(), "M=M+1;JEQ");
+        assert_eq!(disassembler.decode_word(0b1110101010000111).to_string(), "0;JMP");
+    }
+
+    #[test]
+    fn invalid_words_are_data() {
+        let disassembler = Disassembler::default();
+        // the bits after the op code must be 11
+        assert_eq!(disassembler.decode_word(0x8000), Word::Data(0x8000));
+        // 1111111 is not a comp encoding
+        assert_eq!(disassembler.decode_word(0xffff), Word::Data(0xffff));
+        assert_eq!(Word::Data(0xffff).to_string(), "// data: 1111111111111111");
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        let mut source = String::new();
+        for comp in Comp::ALL {
+            for dest in Dest::ALL {
+                source.push_str(&format!("{}={}\n", dest, comp));
+            }
+            for jump in Jump::ALL {
+                source.push_str(&format!("{};{}\n", comp, jump));
+            }
+        }
+        source.push_str("@32767\n");
+        assert_eq!(disassemble(&assemble(&source).unwrap()), source);
+    }
+
+    #[test]
+    fn symbols_round_trip() {
+        let source = "@i\nM=1\n(LOOP)\n@i\nD=M\n@100\nD=D-A\n@END\nD;JGT\n@sum\nM=D+M\n@LOOP\n0;JMP\n(END)\n";
+        let mut diagnostics = Diagnostics::new();
+        let assembly = assemble_source(source, "test.asm", &CDecoder::default(), &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        let symbols = SymbolMap::from_symbol_table(&assembly.symbol_table);
+        assert_eq!(disassemble_with_symbols(&assembly.words, &symbols), source);
+    }
+}
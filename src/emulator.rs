@@ -0,0 +1,319 @@
+use std::convert::TryFrom;
+
+use error::{AsmError, Span};
+use keyboard::KeyScript;
+use screen::SCREEN_WORDS;
+
+/// The number of words of instruction memory
+pub const ROM_SIZE: usize = 0x8000;
+/// The number of words of data memory
+pub const RAM_SIZE: usize = 0x8000;
+/// The base address of the memory-mapped screen
+pub const SCREEN: u16 = 16384;
+/// The address of the memory-mapped keyboard
+pub const KBD: u16 = 24576;
+/// The machine word of 0;JMP, which ends the conventional halting loop
+const HALT_JUMP: u16 = 0xea87;
+
+/// Computes the Hack ALU function selected by the six comp control bits,
+/// zx nx zy ny f no, from the most significant bit down
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control & (1 << n) != 0;
+    let mut x = if bit(5) { 0 } else { x };
+    if bit(4) {
+        x = !x;
+    }
+    let mut y = if bit(3) { 0 } else { y };
+    if bit(2) {
+        y = !y;
+    }
+    let out = if bit(1) { x.wrapping_add(y) } else { x & y };
+    if bit(0) { !out } else { out }
+}
+
+/// A Hack computer: the CPU with its A, D and PC registers, and 32K words
+/// each of ROM and RAM. It runs the machine words the assembler produces.
+///
+/// Like the hardware, every word is executed: the comp bits drive the ALU
+/// directly, so there are no invalid instructions.
+pub struct Emulator {
+    rom: Vec<u16>,
+    ram: Vec<u16>,
+    a: u16,
+    d: u16,
+    pc: u16,
+    program_len: usize,
+    cycles: u64,
+    keys: KeyScript,
+    /// The index of the next key press in `keys`
+    next_key: usize,
+}
+
+impl Emulator {
+    /// Initializes a computer with `program` loaded at the start of ROM
+    ///
+    /// Arguments:
+    ///
+    /// * program - the machine words to load
+    /// * file - the name of the file the program came from, for errors
+    ///
+    /// Returns: the computer, or an error if the program is larger than ROM
+    pub fn new(program: &[u16], file: &str) -> Result<Emulator, AsmError> {
+        if program.len() > ROM_SIZE {
+            return Err(AsmError::ProgramTooLarge { instructions: program.len(), span: Span::file(file) });
+        }
+        let mut rom = vec![0; ROM_SIZE];
+        rom[..program.len()].copy_from_slice(program);
+        Ok(Emulator {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            program_len: program.len(),
+            cycles: 0,
+            keys: KeyScript::default(),
+            next_key: 0,
+        })
+    }
+
+    /// Replays a key script into KBD as the program runs. Key presses
+    /// scheduled for cycles that have already run are applied at the next step.
+    pub fn set_key_script(&mut self, keys: KeyScript) {
+        self.keys = keys;
+        self.next_key = 0;
+    }
+
+    /// Holds down a key, or releases the keyboard with a keycode of 0
+    pub fn press_key(&mut self, keycode: u16) {
+        self.poke(KBD, keycode);
+    }
+
+    /// Executes the instruction at PC, after pressing any keys
+    /// the key script has scheduled for this cycle
+    pub fn step(&mut self) {
+        while let Some(&(cycle, keycode)) = self.keys.events().get(self.next_key) {
+            if cycle > self.cycles {
+                break;
+            }
+            self.press_key(keycode);
+            self.next_key += 1;
+        }
+        let instruction = self.rom[self.pc as usize % ROM_SIZE];
+        self.cycles += 1;
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1);
+            return;
+        }
+        // 111a cccc ccdd djjj
+        let address = self.a as usize % RAM_SIZE;
+        let y = if instruction & 0x1000 != 0 { self.ram[address] } else { self.a };
+        let out = alu(self.d, y, (instruction >> 6) & 0x3f);
+        // the jump and the M write both use A from before this instruction
+        let jump = match out as i16 {
+            value if value < 0 => instruction & 0b100 != 0,
+            0 => instruction & 0b010 != 0,
+            _ => instruction & 0b001 != 0,
+        };
+        self.pc = if jump { self.a } else { self.pc.wrapping_add(1) };
+        if instruction & 0b001000 != 0 {
+            self.ram[address] = out;
+        }
+        if instruction & 0b010000 != 0 {
+            self.d = out;
+        }
+        if instruction & 0b100000 != 0 {
+            self.a = out;
+        }
+    }
+
+    /// Executes instructions until the program halts or `max_cycles` have run
+    ///
+    /// Returns: the number of instructions executed
+    pub fn run(&mut self, max_cycles: u64) -> u64 {
+        let start = self.cycles;
+        while self.cycles - start < max_cycles && !self.is_halted() {
+            self.step();
+        }
+        self.cycles - start
+    }
+
+    /// Whether the program has finished: PC has run past the end of the
+    /// program, or is at the conventional `(END) @END 0;JMP` loop
+    pub fn is_halted(&self) -> bool {
+        let pc = self.pc as usize;
+        if pc >= self.program_len {
+            return true;
+        }
+        // @pc followed by a plain 0;JMP, which can only ever jump back to itself. A jump
+        // that also stores its result, such as M=M+1;JMP, keeps changing state.
+        self.rom[pc] == self.pc && self.rom.get(pc + 1) == Some(&HALT_JUMP)
+    }
+
+    /// Moves PC back to the start of the program, leaving RAM untouched
+    pub fn reset(&mut self) {
+        self.pc = 0;
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn set_a(&mut self, value: u16) {
+        self.a = value;
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn set_d(&mut self, value: u16) {
+        self.d = value;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    /// The number of instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    /// The RAM words mapped to the screen
+    pub fn screen(&self) -> &[u16; SCREEN_WORDS] {
+        let screen = &self.ram[SCREEN as usize..SCREEN as usize + SCREEN_WORDS];
+        <&[u16; SCREEN_WORDS]>::try_from(screen).expect("the screen is SCREEN_WORDS words")
+    }
+
+    /// Reads the RAM word at `address`
+    pub fn peek(&self, address: u16) -> u16 {
+        self.ram[address as usize % RAM_SIZE]
+    }
+
+    /// Writes `value` to the RAM word at `address`
+    pub fn poke(&mut self, address: u16, value: u16) {
+        self.ram[address as usize % RAM_SIZE] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assemble;
+
+    fn emulator(source: &str) -> Emulator {
+        Emulator::new(&assemble(source).unwrap(), "Test.asm").unwrap()
+    }
+
+    #[test]
+    fn every_comp_computes_its_mnemonic() {
+        let (d, a, m) = (17u16, 5u16, 0xfff0u16);
+        let expected = [
+            ("0", 0), ("1", 1), ("-1", 0xffff), ("D", d), ("A", a), ("M", m),
+            ("!D", !d), ("!A", !a), ("!M", !m),
+            ("-D", d.wrapping_neg()), ("-A", a.wrapping_neg()), ("-M", m.wrapping_neg()),
+            ("D+1", d + 1), ("A+1", a + 1), ("M+1", m + 1), ("D-1", d - 1), ("A-1", a - 1), ("M-1", m - 1),
+            ("D+A", d + a), ("D+M", d.wrapping_add(m)), ("D-A", d - a), ("D-M", d.wrapping_sub(m)),
+            ("A-D", a.wrapping_sub(d)), ("M-D", m - d), ("D&A", d & a), ("D&M", d & m),
+            ("D|A", d | a), ("D|M", d | m),
+        ];
+        for &(comp, value) in expected.iter() {
+            let mut emulator = emulator(&format!("@{}\nD=A\n@{}\nD={}\n", d, a, comp));
+            emulator.poke(a, m);
+            emulator.run(4);
+            assert_eq!(emulator.d(), value, "D={}", comp);
+        }
+    }
+
+    #[test]
+    fn run_max() {
+        let source = "@R0\nD=M\n@R1\nD=D-M\n@FIRST\nD;JGT\n@R1\nD=M\n@OUTPUT\n0;JMP\n\
+                      (FIRST)\n@R0\nD=M\n(OUTPUT)\n@R2\nM=D\n(END)\n@END\n0;JMP\n";
+        let mut emulator = emulator(source);
+        emulator.poke(0, 3);
+        emulator.poke(1, 9);
+        let cycles = emulator.run(1000);
+        assert!(emulator.is_halted());
+        assert_eq!(cycles, 12);
+        assert_eq!(emulator.pc(), 14);
+        assert_eq!(emulator.peek(2), 9);
+
+        emulator.reset();
+        emulator.poke(0, 11);
+        emulator.run(1000);
+        assert_eq!(emulator.peek(2), 11);
+    }
+
+    #[test]
+    fn m_writes_use_the_old_a() {
+        // AM=M+1 writes M at the old A, then loads A
+        let mut emulator = emulator("@100\nAM=M+1\n");
+        emulator.poke(100, 6);
+        emulator.run(2);
+        assert_eq!(emulator.peek(100), 7);
+        assert_eq!(emulator.a(), 7);
+        assert!(emulator.is_halted());
+    }
+
+    #[test]
+    fn loop_that_writes_memory_is_not_halted() {
+        let mut emulator = emulator("(LOOP)\n@LOOP\nM=M+1;JMP\n");
+        assert!(!emulator.is_halted());
+        assert_eq!(emulator.run(100), 100);
+        assert_eq!(emulator.peek(0), 50);
+    }
+
+    #[test]
+    fn run_stops_at_max_cycles() {
+        let mut emulator = emulator("(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n");
+        assert_eq!(emulator.run(40), 40);
+        assert!(!emulator.is_halted());
+        assert_eq!(emulator.peek(16), 10);
+        assert_eq!(emulator.cycles(), 40);
+        assert_eq!(emulator.rom()[1], 0xfdc8);
+    }
+
+    #[test]
+    fn key_script_drives_kbd() {
+        // count the cycles the up arrow is held down for
+        let mut emulator = emulator("(LOOP)\n@KBD\nD=M\n@131\nD=D-A\n@LOOP\nD;JNE\n@n\nM=M+1\n@LOOP\n0;JMP\n");
+        emulator.set_key_script(KeyScript::new(vec![(100, 131), (300, 0)]));
+        emulator.run(100);
+        assert_eq!(emulator.peek(KBD), 0);
+        emulator.run(100);
+        assert_eq!(emulator.peek(KBD), 131);
+        emulator.run(1000);
+        assert_eq!(emulator.peek(KBD), 0);
+        assert_eq!(emulator.peek(16), 20);
+    }
+
+    #[test]
+    fn program_larger_than_rom() {
+        assert!(Emulator::new(&vec![0; ROM_SIZE], "Full.hack").is_ok());
+        match Emulator::new(&vec![0; ROM_SIZE + 1], "Big.hack") {
+            Err(AsmError::ProgramTooLarge { instructions, span }) => {
+                assert_eq!(instructions, ROM_SIZE + 1);
+                assert_eq!(span.file, "Big.hack");
+            }
+            _ => panic!("expected ProgramTooLarge"),
+        }
+    }
+}
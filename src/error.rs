@@ -0,0 +1,272 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use emulator::{ROM_SIZE, SCREEN};
+use lexer::is_identifier_char;
+
+/// A location in an assembly source file
+///
+/// `line` is 1-based, and `start..end` is the 0-based byte range of the
+/// offending text within that line. A `line` of 0 refers to the file as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: &str, line: usize, start: usize, end: usize) -> Span {
+        Span {
+            file: file.to_string(),
+            line,
+            start,
+            end,
+        }
+    }
+
+    /// A span covering a whole file, for errors that have no line
+    pub fn file(file: &str) -> Span {
+        Span::new(file, 0, 0, 0)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.file)
+        } else {
+            write!(f, "{}:{}:{}", self.file, self.line, self.start + 1)
+        }
+    }
+}
+
+/// A line of assembly source, used to locate the fields split out of it
+#[derive(Debug, Clone, Copy)]
+pub struct SourceLine<'a> {
+    pub file: &'a str,
+    pub number: usize,
+    pub text: &'a str,
+}
+
+impl<'a> SourceLine<'a> {
+    pub fn new(file: &'a str, number: usize, text: &'a str) -> SourceLine<'a> {
+        SourceLine { file, number, text }
+    }
+
+    /// Returns the span of `token`, which must be a slice of this line's text.
+    /// Falls back to the whole line if it isn't.
+    pub fn span_of(&self, token: &str) -> Span {
+        let line_start = self.text.as_ptr() as usize;
+        let token_start = token.as_ptr() as usize;
+        if token_start >= line_start && token_start + token.len() <= line_start + self.text.len() {
+            let start = token_start - line_start;
+            Span::new(self.file, self.number, start, start + token.len())
+        } else {
+            self.span()
+        }
+    }
+
+    /// Returns the span of the whole line
+    pub fn span(&self) -> Span {
+        Span::new(self.file, self.number, 0, self.text.len())
+    }
+}
+
+/// Errors produced while assembling Hack assembly
+#[derive(Debug)]
+pub enum AsmError {
+    /// The comp field of a C instruction is not a Hack mnemonic
+    UnknownComp { comp: String, span: Span },
+    /// The dest field of a C instruction is not a Hack mnemonic
+    UnknownDest { dest: String, span: Span },
+    /// The jump field of a C instruction is not a Hack mnemonic
+    UnknownJump { jump: String, span: Span },
+    /// An A instruction constant that doesn't fit in 15 bits, or isn't a number
+    AddressOutOfRange { value: String, span: Span },
+    /// A hexadecimal, binary or character constant in standard syntax
+    ExtendedConstant { value: String, span: Span },
+    /// A character constant whose character isn't printable ASCII
+    NonAsciiCharacter { value: String, span: Span },
+    /// A label declaration that isn't of the form (LABEL), and why
+    MalformedLabel { label: String, reason: String, span: Span },
+    /// A label or A instruction symbol that breaks the identifier grammar
+    InvalidSymbol { symbol: String, span: Span },
+    /// Text left over after a complete instruction
+    UnexpectedText { text: String, span: Span },
+    /// An A instruction symbol that was never resolved to an address
+    UndefinedSymbol { symbol: String, span: Span },
+    /// A line of a machine code file that isn't a 16-bit word
+    InvalidWord { text: String, span: Span },
+    /// A line of a symbol map file that isn't of the form "kind name address"
+    InvalidSymbolEntry { text: String, span: Span },
+    /// A line of a key script that isn't of the form "cycle key"
+    InvalidKeyEvent { text: String, span: Span },
+    /// A line of a .vm file that isn't a valid VM command
+    InvalidVmCommand { message: String, span: Span },
+    /// A test script command that can't be parsed or run
+    InvalidScript { message: String, span: Span },
+    /// Jack source that can't be tokenized, parsed or compiled
+    InvalidJack { message: String, span: Span },
+    /// A line of test script output that differs from the compare-to file
+    ComparisonFailure { expected: String, actual: String, span: Span },
+    /// A program with more instructions than ROM holds, located at the first
+    /// instruction that doesn't fit
+    ProgramTooLarge { instructions: usize, span: Span },
+    /// A variable allocated at or past SCREEN, once RAM for variables has run out
+    OutOfVariableMemory { variable: String, address: usize, span: Span },
+    /// A label declared more than once
+    DuplicateLabel { label: String, span: Span, first: Span },
+    /// A warning that a label takes the name of a predefined symbol, such as SCREEN or R5
+    LabelRedefinesPredefined { label: String, span: Span },
+    /// A warning that a new variable differs from a label only in case, which
+    /// usually means the label was meant
+    VariableLikeLabel { variable: String, label: String, span: Span, label_line: usize },
+    /// A failure reading or writing a file
+    Io { error: io::Error, span: Span },
+}
+
+impl AsmError {
+    /// Wraps an I/O error that occurred while accessing `file`
+    pub fn io(error: io::Error, file: &str) -> AsmError {
+        AsmError::Io {
+            error,
+            span: Span::file(file),
+        }
+    }
+
+    /// Returns where the error occurred
+    pub fn span(&self) -> &Span {
+        match *self {
+            AsmError::UnknownComp { ref span, .. }
+            | AsmError::UnknownDest { ref span, .. }
+            | AsmError::UnknownJump { ref span, .. }
+            | AsmError::AddressOutOfRange { ref span, .. }
+            | AsmError::ProgramTooLarge { ref span, .. }
+            | AsmError::OutOfVariableMemory { ref span, .. }
+            | AsmError::ExtendedConstant { ref span, .. }
+            | AsmError::NonAsciiCharacter { ref span, .. }
+            | AsmError::MalformedLabel { ref span, .. }
+            | AsmError::InvalidSymbol { ref span, .. }
+            | AsmError::UnexpectedText { ref span, .. }
+            | AsmError::UndefinedSymbol { ref span, .. }
+            | AsmError::InvalidWord { ref span, .. }
+            | AsmError::InvalidSymbolEntry { ref span, .. }
+            | AsmError::InvalidKeyEvent { ref span, .. }
+            | AsmError::InvalidVmCommand { ref span, .. }
+            | AsmError::InvalidScript { ref span, .. }
+            | AsmError::InvalidJack { ref span, .. }
+            | AsmError::ComparisonFailure { ref span, .. }
+            | AsmError::DuplicateLabel { ref span, .. }
+            | AsmError::LabelRedefinesPredefined { ref span, .. }
+            | AsmError::VariableLikeLabel { ref span, .. }
+            | AsmError::Io { ref span, .. } => span,
+        }
+    }
+
+    /// Returns a second location the error refers to, with a note on what is
+    /// there, such as where a duplicate label was first defined
+    pub fn related(&self) -> Option<(&Span, &'static str)> {
+        match *self {
+            AsmError::DuplicateLabel { ref first, .. } => Some((first, "first defined here")),
+            _ => None,
+        }
+    }
+
+    /// Returns the description of the error, without its location
+    pub fn message(&self) -> String {
+        match *self {
+            AsmError::UnknownComp { ref comp, .. } => format!("unknown comp mnemonic '{}'", comp),
+            AsmError::UnknownDest { ref dest, .. } => format!("unknown dest mnemonic '{}'", dest),
+            AsmError::UnknownJump { ref jump, .. } => format!("unknown jump mnemonic '{}'", jump),
+            AsmError::AddressOutOfRange { ref value, .. } => {
+                format!("address '{}' is not a constant between 0 and 32767", value)
+            }
+            AsmError::ProgramTooLarge { instructions, .. } => {
+                format!("the program has {} instructions, but ROM only holds {}", instructions, ROM_SIZE)
+            }
+            AsmError::OutOfVariableMemory { ref variable, address, .. } => {
+                format!("variable '{}' would be at address {}, but variables must end before SCREEN at {}",
+                        variable, address, SCREEN)
+            }
+            AsmError::ExtendedConstant { ref value, .. } => {
+                format!("'{}' is only a constant in extended syntax", value)
+            }
+            AsmError::NonAsciiCharacter { ref value, .. } => {
+                format!("character constant {} is not a printable ASCII character", value)
+            }
+            AsmError::MalformedLabel { ref label, ref reason, .. } => format!("malformed label '{}': {}", label, reason),
+            AsmError::InvalidSymbol { ref symbol, .. } if symbol.is_empty() => {
+                "'@' needs a symbol or a constant after it".to_string()
+            }
+            AsmError::InvalidSymbol { ref symbol, .. } => {
+                match symbol.chars().find(|&c| !is_identifier_char(c)) {
+                    Some(c) => format!("symbol '{}' contains '{}', but symbols can only have letters, digits, \
+                                        '_', '.', '$' and ':'", symbol, c),
+                    None => format!("symbol '{}' starts with a digit", symbol),
+                }
+            }
+            AsmError::UnexpectedText { ref text, .. } => format!("unexpected '{}' after the instruction", text),
+            AsmError::UndefinedSymbol { ref symbol, .. } => format!("undefined symbol '{}'", symbol),
+            AsmError::InvalidWord { ref text, .. } => format!("'{}' is not a 16-bit machine word", text),
+            AsmError::InvalidSymbolEntry { ref text, .. } => format!("'{}' is not a symbol map entry", text),
+            AsmError::InvalidKeyEvent { ref text, .. } => format!("'{}' is not a key press of the form 'cycle key'", text),
+            AsmError::InvalidVmCommand { ref message, .. }
+            | AsmError::InvalidScript { ref message, .. }
+            | AsmError::InvalidJack { ref message, .. } => message.clone(),
+            AsmError::ComparisonFailure { ref expected, ref actual, .. } => {
+                format!("comparison failure: expected '{}', found '{}'", expected, actual)
+            }
+            AsmError::DuplicateLabel { ref label, ref first, .. } => {
+                format!("label '{}' is already defined at {}", label, first)
+            }
+            AsmError::LabelRedefinesPredefined { ref label, .. } => {
+                format!("label '{}' redefines a predefined symbol, which keeps its address", label)
+            }
+            AsmError::VariableLikeLabel { ref variable, ref label, label_line, .. } => {
+                format!("variable '{}' differs only in case from label '{}' on line {}", variable, label, label_line)
+            }
+            AsmError::Io { ref error, .. } => error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span(), self.message())
+    }
+}
+
+impl Error for AsmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            AsmError::Io { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_of_token() {
+        let text = "D=D+X;JMP";
+        let line = SourceLine::new("Max.asm", 3, text);
+        assert_eq!(line.span_of(&text[2..5]), Span::new("Max.asm", 3, 2, 5));
+        let elsewhere = String::from("D+X");
+        assert_eq!(line.span_of(&elsewhere), Span::new("Max.asm", 3, 0, 9));
+    }
+
+    #[test]
+    fn display_error() {
+        let err = AsmError::UnknownComp {
+            comp: "D+X".to_string(),
+            span: Span::new("Max.asm", 3, 2, 5),
+        };
+        assert_eq!(err.to_string(), "Max.asm:3:3: unknown comp mnemonic 'D+X'");
+    }
+}
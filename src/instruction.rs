@@ -0,0 +1,239 @@
+use std::fmt;
+
+/// Defines an enum of Hack mnemonics, along with the conversions
+/// between each variant and its assembly text
+macro_rules! mnemonics {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $text:expr,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            /// Every mnemonic, in the order of the Hack specification
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+
+            /// Returns the assembly text of the mnemonic
+            pub fn mnemonic(&self) -> &'static str {
+                match *self {
+                    $($name::$variant => $text,)*
+                }
+            }
+
+            /// Looks up a mnemonic by its assembly text
+            pub fn from_mnemonic(text: &str) -> Option<$name> {
+                match text {
+                    $($text => Some($name::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(self.mnemonic())
+            }
+        }
+    };
+}
+
+mnemonics! {
+    /// The registers a C instruction stores its result in
+    Dest {
+        M => "M",
+        D => "D",
+        MD => "MD",
+        A => "A",
+        AM => "AM",
+        AD => "AD",
+        AMD => "AMD",
+    }
+}
+
+mnemonics! {
+    /// The computations of the Hack ALU
+    Comp {
+        Zero => "0",
+        One => "1",
+        MinusOne => "-1",
+        D => "D",
+        A => "A",
+        M => "M",
+        NotD => "!D",
+        NotA => "!A",
+        NotM => "!M",
+        NegD => "-D",
+        NegA => "-A",
+        NegM => "-M",
+        DPlusOne => "D+1",
+        APlusOne => "A+1",
+        MPlusOne => "M+1",
+        DMinusOne => "D-1",
+        AMinusOne => "A-1",
+        MMinusOne => "M-1",
+        DPlusA => "D+A",
+        DPlusM => "D+M",
+        DMinusA => "D-A",
+        DMinusM => "D-M",
+        AMinusD => "A-D",
+        MMinusD => "M-D",
+        DAndA => "D&A",
+        DAndM => "D&M",
+        DOrA => "D|A",
+        DOrM => "D|M",
+    }
+}
+
+mnemonics! {
+    /// The conditions a C instruction jumps on
+    Jump {
+        JGT => "JGT",
+        JEQ => "JEQ",
+        JGE => "JGE",
+        JLT => "JLT",
+        JNE => "JNE",
+        JLE => "JLE",
+        JMP => "JMP",
+    }
+}
+
+impl Dest {
+    /// Looks up a dest by its registers in any order, so "DM" and "MAD" are
+    /// read as MD and AMD. Each register may only appear once.
+    pub fn from_registers(text: &str) -> Option<Dest> {
+        let mut registers: Vec<char> = text.chars().collect();
+        // the mnemonics list their registers in the order A, M, D
+        registers.sort_by_key(|&register| "AMD".find(register));
+        registers.dedup();
+        if registers.len() != text.len() {
+            return None;
+        }
+        Dest::from_mnemonic(&registers.into_iter().collect::<String>())
+    }
+}
+
+impl Comp {
+    /// Looks up a computation, also accepting the operands of the commutative
+    /// operators '+', '&' and '|' in either order, so "A+D" is read as D+A
+    pub fn from_any_order(text: &str) -> Option<Comp> {
+        Comp::from_mnemonic(text).or_else(|| {
+            let at = text.find(|c| "+&|".contains(c))?;
+            let (left, right) = (&text[..at], &text[at + 1..]);
+            if left.is_empty() || right.is_empty() {
+                return None;
+            }
+            Comp::from_mnemonic(&format!("{}{}{}", right, &text[at..at + 1], left))
+        })
+    }
+}
+
+/// The operand of an A instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A numeric constant
+    Constant(u16),
+    /// A label, variable or predefined symbol
+    Symbol(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Constant(value) => write!(f, "{}", value),
+            Value::Symbol(ref symbol) => f.write_str(symbol),
+        }
+    }
+}
+
+/// A parsed line of Hack assembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// @value
+    A(Value),
+    /// dest=comp;jump, where dest and jump are optional
+    C {
+        dest: Option<Dest>,
+        comp: Comp,
+        jump: Option<Jump>,
+    },
+    /// (name), a label declaration that takes up no ROM
+    Label(String),
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::A(ref value) => write!(f, "@{}", value),
+            Instruction::C { dest, comp, jump } => {
+                if let Some(dest) = dest {
+                    write!(f, "{}=", dest)?;
+                }
+                write!(f, "{}", comp)?;
+                if let Some(jump) = jump {
+                    write!(f, ";{}", jump)?;
+                }
+                Ok(())
+            }
+            Instruction::Label(ref name) => write!(f, "({})", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mnemonics_round_trip() {
+        assert_eq!(Comp::ALL.len(), 28);
+        for comp in Comp::ALL {
+            assert_eq!(Comp::from_mnemonic(comp.mnemonic()), Some(*comp));
+        }
+        for dest in Dest::ALL {
+            assert_eq!(Dest::from_mnemonic(dest.mnemonic()), Some(*dest));
+        }
+        for jump in Jump::ALL {
+            assert_eq!(Jump::from_mnemonic(jump.mnemonic()), Some(*jump));
+        }
+        assert_eq!(Comp::from_mnemonic("D+X"), None);
+    }
+
+    #[test]
+    fn commuted_comps() {
+        assert_eq!(Comp::from_any_order("A+D"), Some(Comp::DPlusA));
+        assert_eq!(Comp::from_any_order("M+D"), Some(Comp::DPlusM));
+        assert_eq!(Comp::from_any_order("A&D"), Some(Comp::DAndA));
+        assert_eq!(Comp::from_any_order("M|D"), Some(Comp::DOrM));
+        assert_eq!(Comp::from_any_order("1+M"), Some(Comp::MPlusOne));
+        assert_eq!(Comp::from_any_order("D|A"), Some(Comp::DOrA));
+        // subtraction doesn't commute
+        assert_eq!(Comp::from_any_order("1-D"), None);
+        assert_eq!(Comp::from_any_order("+D"), None);
+        assert_eq!(Comp::from_any_order("A+M"), None);
+    }
+
+    #[test]
+    fn dest_registers_in_any_order() {
+        assert_eq!(Dest::from_registers("DM"), Some(Dest::MD));
+        assert_eq!(Dest::from_registers("MA"), Some(Dest::AM));
+        assert_eq!(Dest::from_registers("DA"), Some(Dest::AD));
+        assert_eq!(Dest::from_registers("MAD"), Some(Dest::AMD));
+        assert_eq!(Dest::from_registers("DMA"), Some(Dest::AMD));
+        assert_eq!(Dest::from_registers("M"), Some(Dest::M));
+        assert_eq!(Dest::from_registers("MM"), None);
+        assert_eq!(Dest::from_registers("MX"), None);
+        assert_eq!(Dest::from_registers(""), None);
+    }
+
+    #[test]
+    fn display_instructions() {
+        let c = Instruction::C { dest: Some(Dest::MD), comp: Comp::DPlusOne, jump: Some(Jump::JLE) };
+        assert_eq!(c.to_string(), "MD=D+1;JLE");
+        let c = Instruction::C { dest: None, comp: Comp::Zero, jump: Some(Jump::JMP) };
+        assert_eq!(c.to_string(), "0;JMP");
+        assert_eq!(Instruction::A(Value::Constant(16)).to_string(), "@16");
+        assert_eq!(Instruction::A(Value::Symbol("sum".to_string())).to_string(), "@sum");
+        assert_eq!(Instruction::Label("LOOP".to_string()).to_string(), "(LOOP)");
+    }
+}
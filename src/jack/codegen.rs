@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use diagnostics::Diagnostics;
+use error::{AsmError, Span};
+use jack::parser::{parse_class, Class, ClassVarKind, Expression, KeywordConstant, Statement, Subroutine,
+                   SubroutineCall, SubroutineKind, Term, Type};
+use vm::{ArithmeticOp, Segment, VmCommand};
+
+/// A variable in scope: its type, and where the VM keeps it
+#[derive(Debug, Clone)]
+struct Variable {
+    ty: Type,
+    segment: Segment,
+    index: u16,
+}
+
+/// Compiles a parsed class into VM commands, one subroutine at a time
+struct CodeGenerator<'a> {
+    class: &'a Class,
+    fields: u16,
+    class_vars: HashMap<String, Variable>,
+    /// The arguments and locals of the current subroutine
+    locals: HashMap<String, Variable>,
+    kind: SubroutineKind,
+    labels: usize,
+    commands: Vec<VmCommand>,
+}
+
+impl<'a> CodeGenerator<'a> {
+    fn new(class: &'a Class) -> CodeGenerator<'a> {
+        let mut class_vars = HashMap::new();
+        let (mut statics, mut fields) = (0, 0);
+        for dec in class.vars.iter() {
+            for name in dec.names.iter() {
+                let (segment, counter) = match dec.kind {
+                    ClassVarKind::Static => (Segment::Static, &mut statics),
+                    ClassVarKind::Field => (Segment::This, &mut fields),
+                };
+                class_vars.insert(name.clone(), Variable { ty: dec.ty.clone(), segment, index: *counter });
+                *counter += 1;
+            }
+        }
+        CodeGenerator {
+            class,
+            fields,
+            class_vars,
+            locals: HashMap::new(),
+            kind: SubroutineKind::Function,
+            labels: 0,
+            commands: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, command: VmCommand) {
+        self.commands.push(command);
+    }
+
+    fn error(&self, message: String, span: &Span) -> AsmError {
+        AsmError::InvalidJack { message, span: span.clone() }
+    }
+
+    fn label(&mut self, kind: &str) -> String {
+        let label = format!("{}{}", kind, self.labels);
+        self.labels += 1;
+        label
+    }
+
+    fn lookup(&self, name: &str, span: &Span) -> Result<Variable, AsmError> {
+        let variable = self.locals.get(name).or_else(|| self.class_vars.get(name))
+            .ok_or_else(|| self.error(format!("undefined variable '{}'", name), span))?;
+        if variable.segment == Segment::This && self.kind == SubroutineKind::Function {
+            return Err(self.error(format!("field '{}' is used in a function", name), span));
+        }
+        Ok(variable.clone())
+    }
+
+    fn compile_subroutine(&mut self, subroutine: &Subroutine) -> Result<(), AsmError> {
+        self.kind = subroutine.kind;
+        self.labels = 0;
+        self.locals.clear();
+        // a method's object is its argument 0
+        let first_argument = if subroutine.kind == SubroutineKind::Method { 1 } else { 0 };
+        for (index, (ty, name)) in subroutine.parameters.iter().enumerate() {
+            let variable = Variable { ty: ty.clone(), segment: Segment::Argument, index: first_argument + index as u16 };
+            self.locals.insert(name.clone(), variable);
+        }
+        for (index, (ty, name)) in subroutine.locals.iter().enumerate() {
+            let variable = Variable { ty: ty.clone(), segment: Segment::Local, index: index as u16 };
+            self.locals.insert(name.clone(), variable);
+        }
+
+        let name = format!("{}.{}", self.class.name, subroutine.name);
+        self.emit(VmCommand::Function(name, subroutine.locals.len() as u16));
+        match subroutine.kind {
+            SubroutineKind::Constructor => {
+                self.emit(VmCommand::Push(Segment::Constant, self.fields));
+                self.emit(VmCommand::Call("Memory.alloc".to_string(), 1));
+                self.emit(VmCommand::Pop(Segment::Pointer, 0));
+            }
+            SubroutineKind::Method => {
+                self.emit(VmCommand::Push(Segment::Argument, 0));
+                self.emit(VmCommand::Pop(Segment::Pointer, 0));
+            }
+            SubroutineKind::Function => {}
+        }
+        self.compile_statements(&subroutine.statements)
+    }
+
+    fn compile_statements(&mut self, statements: &[Statement]) -> Result<(), AsmError> {
+        for statement in statements.iter() {
+            self.compile_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), AsmError> {
+        match *statement {
+            Statement::Let { ref name, ref span, index: None, ref value } => {
+                let variable = self.lookup(name, span)?;
+                self.compile_expression(value)?;
+                self.emit(VmCommand::Pop(variable.segment, variable.index));
+            }
+            Statement::Let { ref name, ref span, index: Some(ref index), ref value } => {
+                self.compile_element_address(name, span, index)?;
+                // the value may itself index an array, so THAT is set only after it's computed
+                self.compile_expression(value)?;
+                self.emit(VmCommand::Pop(Segment::Temp, 0));
+                self.emit(VmCommand::Pop(Segment::Pointer, 1));
+                self.emit(VmCommand::Push(Segment::Temp, 0));
+                self.emit(VmCommand::Pop(Segment::That, 0));
+            }
+            Statement::If { ref condition, ref then, ref otherwise } => {
+                let (else_label, end_label) = (self.label("IF_ELSE"), self.label("IF_END"));
+                self.compile_expression(condition)?;
+                self.emit(VmCommand::Arithmetic(ArithmeticOp::Not));
+                self.emit(VmCommand::IfGoto(else_label.clone()));
+                self.compile_statements(then)?;
+                match *otherwise {
+                    Some(ref otherwise) => {
+                        self.emit(VmCommand::Goto(end_label.clone()));
+                        self.emit(VmCommand::Label(else_label));
+                        self.compile_statements(otherwise)?;
+                        self.emit(VmCommand::Label(end_label));
+                    }
+                    None => self.emit(VmCommand::Label(else_label)),
+                }
+            }
+            Statement::While { ref condition, ref body } => {
+                let (loop_label, end_label) = (self.label("WHILE_EXP"), self.label("WHILE_END"));
+                self.emit(VmCommand::Label(loop_label.clone()));
+                self.compile_expression(condition)?;
+                self.emit(VmCommand::Arithmetic(ArithmeticOp::Not));
+                self.emit(VmCommand::IfGoto(end_label.clone()));
+                self.compile_statements(body)?;
+                self.emit(VmCommand::Goto(loop_label));
+                self.emit(VmCommand::Label(end_label));
+            }
+            Statement::Do(ref call) => {
+                self.compile_call(call)?;
+                self.emit(VmCommand::Pop(Segment::Temp, 0));
+            }
+            Statement::Return(ref value) => {
+                match *value {
+                    Some(ref value) => self.compile_expression(value)?,
+                    None => self.emit(VmCommand::Push(Segment::Constant, 0)),
+                }
+                self.emit(VmCommand::Return);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes the address of name[index]
+    fn compile_element_address(&mut self, name: &str, span: &Span, index: &Expression) -> Result<(), AsmError> {
+        let variable = self.lookup(name, span)?;
+        self.emit(VmCommand::Push(variable.segment, variable.index));
+        self.compile_expression(index)?;
+        self.emit(VmCommand::Arithmetic(ArithmeticOp::Add));
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &Expression) -> Result<(), AsmError> {
+        self.compile_term(&expression.first)?;
+        for (op, term) in expression.rest.iter() {
+            self.compile_term(term)?;
+            let command = match *op {
+                '+' => VmCommand::Arithmetic(ArithmeticOp::Add),
+                '-' => VmCommand::Arithmetic(ArithmeticOp::Sub),
+                '&' => VmCommand::Arithmetic(ArithmeticOp::And),
+                '|' => VmCommand::Arithmetic(ArithmeticOp::Or),
+                '<' => VmCommand::Arithmetic(ArithmeticOp::Lt),
+                '>' => VmCommand::Arithmetic(ArithmeticOp::Gt),
+                '=' => VmCommand::Arithmetic(ArithmeticOp::Eq),
+                '*' => VmCommand::Call("Math.multiply".to_string(), 2),
+                _ => VmCommand::Call("Math.divide".to_string(), 2),
+            };
+            self.emit(command);
+        }
+        Ok(())
+    }
+
+    fn compile_term(&mut self, term: &Term) -> Result<(), AsmError> {
+        match *term {
+            Term::Integer(value) => self.emit(VmCommand::Push(Segment::Constant, value)),
+            Term::Str(ref text) => {
+                self.emit(VmCommand::Push(Segment::Constant, text.chars().count() as u16));
+                self.emit(VmCommand::Call("String.new".to_string(), 1));
+                for c in text.chars() {
+                    self.emit(VmCommand::Push(Segment::Constant, c as u16));
+                    self.emit(VmCommand::Call("String.appendChar".to_string(), 2));
+                }
+            }
+            Term::Keyword(KeywordConstant::True) => {
+                self.emit(VmCommand::Push(Segment::Constant, 0));
+                self.emit(VmCommand::Arithmetic(ArithmeticOp::Not));
+            }
+            Term::Keyword(KeywordConstant::False) | Term::Keyword(KeywordConstant::Null) => {
+                self.emit(VmCommand::Push(Segment::Constant, 0));
+            }
+            Term::Keyword(KeywordConstant::This) => self.emit(VmCommand::Push(Segment::Pointer, 0)),
+            Term::Var(ref name, ref span) => {
+                let variable = self.lookup(name, span)?;
+                self.emit(VmCommand::Push(variable.segment, variable.index));
+            }
+            Term::Index(ref name, ref span, ref index) => {
+                self.compile_element_address(name, span, index)?;
+                self.emit(VmCommand::Pop(Segment::Pointer, 1));
+                self.emit(VmCommand::Push(Segment::That, 0));
+            }
+            Term::Call(ref call) => self.compile_call(call)?,
+            Term::Parenthesized(ref expression) => self.compile_expression(expression)?,
+            Term::Unary(op, ref term) => {
+                self.compile_term(term)?;
+                let op = if op == '-' { ArithmeticOp::Neg } else { ArithmeticOp::Not };
+                self.emit(VmCommand::Arithmetic(op));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a call, which is one of:
+    ///
+    /// * f(...) - a function of this class, or a method on this object
+    /// * variable.f(...) - a method on the object in the variable
+    /// * Class.f(...) - a function or constructor of another class
+    fn compile_call(&mut self, call: &SubroutineCall) -> Result<(), AsmError> {
+        let receiver = match call.receiver {
+            Some(ref receiver) if self.locals.contains_key(receiver) || self.class_vars.contains_key(receiver) => {
+                let variable = self.lookup(receiver, &call.span)?;
+                match variable.ty {
+                    Type::Class(ref class) => {
+                        self.emit(VmCommand::Push(variable.segment, variable.index));
+                        Some(class.clone())
+                    }
+                    _ => {
+                        return Err(self.error(format!("cannot call method '{}' on '{}', which isn't an object",
+                                                      call.name, receiver), &call.span));
+                    }
+                }
+            }
+            Some(ref class) => {
+                self.compile_arguments(&call.arguments)?;
+                self.emit(VmCommand::Call(format!("{}.{}", class, call.name), call.arguments.len() as u16));
+                return Ok(());
+            }
+            None => {
+                let callee = self.class.subroutines.iter().find(|subroutine| subroutine.name == call.name);
+                if callee.is_none_or(|callee| callee.kind == SubroutineKind::Method) {
+                    if self.kind == SubroutineKind::Function {
+                        return Err(self.error(format!("method '{}' is called from a function", call.name),
+                                              &call.span));
+                    }
+                    self.emit(VmCommand::Push(Segment::Pointer, 0));
+                    Some(self.class.name.clone())
+                } else {
+                    self.compile_arguments(&call.arguments)?;
+                    let name = format!("{}.{}", self.class.name, call.name);
+                    self.emit(VmCommand::Call(name, call.arguments.len() as u16));
+                    return Ok(());
+                }
+            }
+        };
+        // a method call, with the object already pushed as argument 0
+        self.compile_arguments(&call.arguments)?;
+        let class = receiver.unwrap();
+        self.emit(VmCommand::Call(format!("{}.{}", class, call.name), call.arguments.len() as u16 + 1));
+        Ok(())
+    }
+
+    fn compile_arguments(&mut self, arguments: &[Expression]) -> Result<(), AsmError> {
+        for argument in arguments.iter() {
+            self.compile_expression(argument)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compiles a .jack file into VM code
+///
+/// Arguments:
+///
+/// * source - the Jack source, which holds one class
+/// * file_name - the name of the source, used in errors
+/// * diagnostics - collects the errors in the source
+///
+/// Returns: the VM code, which is incomplete if any errors were found
+pub fn compile_jack(source: &str, file_name: &str, diagnostics: &mut Diagnostics) -> String {
+    let class = match parse_class(source, file_name) {
+        Ok(class) => class,
+        Err(e) => {
+            diagnostics.push(e);
+            return String::new();
+        }
+    };
+    let mut generator = CodeGenerator::new(&class);
+    for subroutine in class.subroutines.iter() {
+        if let Err(e) = generator.compile_subroutine(subroutine) {
+            diagnostics.push(e);
+        }
+    }
+    let mut vm = String::new();
+    for command in generator.commands.iter() {
+        let _ = writeln!(vm, "{}", command);
+    }
+    vm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assemble;
+    use emulator::Emulator;
+    use vm::translate_vm;
+
+    const SYS: &str = "\
+function Sys.init 0
+call Main.main 0
+pop temp 0
+label HALT
+goto HALT
+";
+
+    /// A bump allocator standing in for the OS's Memory class
+    const MEMORY: &str = "\
+function Memory.alloc 0
+push static 0
+if-goto ALLOC
+push constant 2048
+pop static 0
+label ALLOC
+push static 0
+push static 0
+push argument 0
+add
+pop static 0
+return
+";
+
+    const MAIN: &str = "
+class Main {
+    /** Returns 7 * (0 + 1 + 2 + 3 + 4) */
+    function int main() {
+        var Point p;
+        var Array a;
+        var int i, sum;
+        let p = Point.new(3, 4);
+        let a = Memory.alloc(5);
+        let i = 0;
+        while (i < 5) {
+            let a[i] = p.scaled(i);
+            let i = i + 1;
+        }
+        let sum = 0;
+        let i = 0;
+        while (~(i = 5)) {
+            let sum = sum + a[i];
+            let i = i + 1;
+        }
+        if (sum > 100) {
+            return -1;
+        } else {
+            return sum;
+        }
+    }
+}
+";
+
+    const POINT: &str = "
+class Point {
+    field int x, y;
+
+    constructor Point new(int ax, int ay) {
+        let x = ax;
+        let y = ay;
+        return this;
+    }
+
+    method int scaled(int n) {
+        return (x + y) * n;
+    }
+}
+";
+
+    const MATH: &str = "
+class Math {
+    function int multiply(int a, int b) {
+        var int product;
+        let product = 0;
+        while (b > 0) {
+            let product = product + a;
+            let b = b - 1;
+        }
+        return product;
+    }
+}
+";
+
+    fn compile(source: &str) -> Result<String, Vec<AsmError>> {
+        let mut diagnostics = Diagnostics::new();
+        let vm = compile_jack(source, "Main.jack", &mut diagnostics);
+        if diagnostics.has_errors() {
+            return Err(diagnostics.into_errors());
+        }
+        Ok(vm)
+    }
+
+    #[test]
+    fn compile_and_run() {
+        let (main, point, math) = (compile(MAIN).unwrap(), compile(POINT).unwrap(), compile(MATH).unwrap());
+        let files = [("Sys.vm", SYS), ("Memory.vm", MEMORY), ("Main.vm", &main[..]), ("Point.vm", &point[..]),
+                     ("Math.vm", &math[..])];
+        let mut diagnostics = Diagnostics::new();
+        let asm = translate_vm(&files, &mut diagnostics);
+        assert!(!diagnostics.has_errors(), "{:?}", diagnostics.errors());
+        let mut emulator = Emulator::new(&assemble(&asm).unwrap(), "Test.asm").unwrap();
+        emulator.run(100_000);
+        // Sys.init pops the return value of Main.main into temp 0
+        assert_eq!(emulator.peek(5), 70);
+    }
+
+    #[test]
+    fn compile_constants() {
+        let vm = compile("class Main { function boolean f() { do Output.printString(\"ok\"); return true; } }");
+        assert_eq!(vm.unwrap(), "\
+function Main.f 0
+push constant 2
+call String.new 1
+push constant 111
+call String.appendChar 2
+push constant 107
+call String.appendChar 2
+call Output.printString 1
+pop temp 0
+push constant 0
+not
+return
+");
+    }
+
+    #[test]
+    fn compile_errors() {
+        let errors = compile("class Main {\n  field int x;\n  function void f() {\n    let y = 1;\n    let x = 2;\n\
+                              do x.g();\n    do h();\n    return;\n  }\n  method void h() { return; }\n}").unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec!["Main.jack:4:9: undefined variable 'y'"]);
+
+        let message = |source: &str| compile(source).unwrap_err()[0].to_string();
+        assert_eq!(message("class Main { field int x; function void f() { let x = 2; return; } }"),
+                   "Main.jack:1:51: field 'x' is used in a function");
+        assert_eq!(message("class Main { method void f() { var int x; do x.g(); return; } }"),
+                   "Main.jack:1:46: cannot call method 'g' on 'x', which isn't an object");
+        assert_eq!(message("class Main { function void f() { do h(); return; } method void h() { return; } }"),
+                   "Main.jack:1:37: method 'h' is called from a function");
+    }
+}
@@ -0,0 +1,10 @@
+//! A compiler from Jack, the course's object-based language, to VM code
+
+mod codegen;
+mod parser;
+mod tokenizer;
+
+pub use self::codegen::compile_jack;
+pub use self::parser::{parse_class, parse_tree_xml, Class, ClassVarDec, ClassVarKind, Expression, KeywordConstant,
+                       Statement, Subroutine, SubroutineCall, SubroutineKind, Term, Type};
+pub use self::tokenizer::{tokenize, tokens_xml, Token, TokenKind, KEYWORDS};
@@ -0,0 +1,595 @@
+use error::{AsmError, Span};
+use jack::tokenizer::{escape_xml, tokenize, Token, TokenKind};
+
+/// The type of a variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    Class(String),
+}
+
+/// Whether a class variable is shared by the class or belongs to each object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassVarKind {
+    Static,
+    Field,
+}
+
+/// static|field type name, name, ...;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassVarDec {
+    pub kind: ClassVarKind,
+    pub ty: Type,
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+/// A constructor, function or method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    pub kind: SubroutineKind,
+    /// The return type, or None for void
+    pub return_type: Option<Type>,
+    pub name: String,
+    pub parameters: Vec<(Type, String)>,
+    pub locals: Vec<(Type, String)>,
+    pub statements: Vec<Statement>,
+}
+
+/// A parsed .jack file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Class {
+    pub name: String,
+    pub vars: Vec<ClassVarDec>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    /// let name[index] = value;
+    Let { name: String, span: Span, index: Option<Expression>, value: Expression },
+    If { condition: Expression, then: Vec<Statement>, otherwise: Option<Vec<Statement>> },
+    While { condition: Expression, body: Vec<Statement> },
+    Do(SubroutineCall),
+    Return(Option<Expression>),
+}
+
+/// term (op term)*, evaluated from left to right
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    pub first: Box<Term>,
+    pub rest: Vec<(char, Term)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeywordConstant {
+    True,
+    False,
+    Null,
+    This,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    Integer(u16),
+    Str(String),
+    Keyword(KeywordConstant),
+    Var(String, Span),
+    /// name[index]
+    Index(String, Span, Expression),
+    Call(SubroutineCall),
+    Parenthesized(Expression),
+    /// - or ~ applied to a term
+    Unary(char, Box<Term>),
+}
+
+/// name(arguments) or receiver.name(arguments), where the receiver
+/// is a class or a variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubroutineCall {
+    pub receiver: Option<String>,
+    pub name: String,
+    pub span: Span,
+    pub arguments: Vec<Expression>,
+}
+
+/// A recursive descent parser for Jack, which can also write the course's
+/// parse tree XML (Xxx.xml) as it goes, as the project 10 compilation engine does
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    file_name: String,
+    xml: Option<String>,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.position).map(|token| &token.kind)
+    }
+
+    fn peek_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&TokenKind::Symbol(symbol))
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(&TokenKind::Keyword(k)) if k == keyword)
+    }
+
+    /// The span of the current token, or the end of the file
+    fn span(&self) -> Span {
+        match self.tokens.get(self.position).or(self.tokens.last()) {
+            Some(token) if self.position < self.tokens.len() => token.span.clone(),
+            Some(token) => Span::new(&self.file_name, token.span.line, token.span.end, token.span.end),
+            None => Span::file(&self.file_name),
+        }
+    }
+
+    fn error(&self, expected: &str) -> AsmError {
+        let found = match self.peek() {
+            Some(kind) => format!("'{}'", kind),
+            None => "end of file".to_string(),
+        };
+        AsmError::InvalidJack {
+            message: format!("expected {}, found {}", expected, found),
+            span: self.span(),
+        }
+    }
+
+    fn indent(&mut self, line: &str) {
+        if let Some(ref mut xml) = self.xml {
+            xml.push_str(&"  ".repeat(self.depth));
+            xml.push_str(line);
+            xml.push('\n');
+        }
+    }
+
+    /// Opens a non-terminal element of the parse tree
+    fn open(&mut self, rule: &str) {
+        self.indent(&format!("<{}>", rule));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, rule: &str) {
+        self.depth -= 1;
+        self.indent(&format!("</{}>", rule));
+    }
+
+    /// Consumes the current token, recording it as a terminal of the parse tree
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        self.position += 1;
+        let tag = token.kind.xml_tag();
+        self.indent(&format!("<{}> {} </{}>", tag, escape_xml(&token.kind.to_string()), tag));
+        token
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), AsmError> {
+        if !self.peek_symbol(symbol) {
+            return Err(self.error(&format!("'{}'", symbol)));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), AsmError> {
+        if !self.peek_keyword(keyword) {
+            return Err(self.error(&format!("'{}'", keyword)));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn identifier(&mut self, what: &str) -> Result<(String, Span), AsmError> {
+        match self.peek() {
+            Some(&TokenKind::Identifier(_)) => {
+                let token = self.advance();
+                Ok((token.kind.to_string(), token.span))
+            }
+            _ => Err(self.error(what)),
+        }
+    }
+
+    /// type: int | char | boolean | className
+    fn parse_type(&mut self, allow_void: bool) -> Result<Option<Type>, AsmError> {
+        let ty = match self.peek() {
+            Some(&TokenKind::Keyword("int")) => Type::Int,
+            Some(&TokenKind::Keyword("char")) => Type::Char,
+            Some(&TokenKind::Keyword("boolean")) => Type::Boolean,
+            Some(&TokenKind::Keyword("void")) if allow_void => {
+                self.advance();
+                return Ok(None);
+            }
+            Some(TokenKind::Identifier(name)) => Type::Class(name.clone()),
+            _ => return Err(self.error("a type")),
+        };
+        self.advance();
+        Ok(Some(ty))
+    }
+
+    /// type name (, name)*
+    fn parse_names(&mut self) -> Result<(Type, Vec<String>), AsmError> {
+        let ty = self.parse_type(false)?.unwrap();
+        let mut names = vec![self.identifier("a variable name")?.0];
+        while self.peek_symbol(',') {
+            self.advance();
+            names.push(self.identifier("a variable name")?.0);
+        }
+        Ok((ty, names))
+    }
+
+    fn parse_class(&mut self) -> Result<Class, AsmError> {
+        self.open("class");
+        self.expect_keyword("class")?;
+        let (name, _) = self.identifier("a class name")?;
+        self.expect_symbol('{')?;
+        let mut vars = Vec::new();
+        while self.peek_keyword("static") || self.peek_keyword("field") {
+            self.open("classVarDec");
+            let kind = if self.peek_keyword("static") { ClassVarKind::Static } else { ClassVarKind::Field };
+            self.advance();
+            let (ty, names) = self.parse_names()?;
+            self.expect_symbol(';')?;
+            self.close("classVarDec");
+            vars.push(ClassVarDec { kind, ty, names });
+        }
+        let mut subroutines = Vec::new();
+        while !self.peek_symbol('}') {
+            subroutines.push(self.parse_subroutine()?);
+        }
+        self.expect_symbol('}')?;
+        self.close("class");
+        if self.position < self.tokens.len() {
+            return Err(self.error("end of file after the class"));
+        }
+        Ok(Class { name, vars, subroutines })
+    }
+
+    fn parse_subroutine(&mut self) -> Result<Subroutine, AsmError> {
+        let kind = match self.peek() {
+            Some(&TokenKind::Keyword("constructor")) => SubroutineKind::Constructor,
+            Some(&TokenKind::Keyword("function")) => SubroutineKind::Function,
+            Some(&TokenKind::Keyword("method")) => SubroutineKind::Method,
+            _ => return Err(self.error("a subroutine declaration or '}'")),
+        };
+        self.open("subroutineDec");
+        self.advance();
+        let return_type = self.parse_type(true)?;
+        let (name, _) = self.identifier("a subroutine name")?;
+        self.expect_symbol('(')?;
+        self.open("parameterList");
+        let mut parameters = Vec::new();
+        if !self.peek_symbol(')') {
+            loop {
+                let ty = self.parse_type(false)?.unwrap();
+                parameters.push((ty, self.identifier("a parameter name")?.0));
+                if !self.peek_symbol(',') {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.close("parameterList");
+        self.expect_symbol(')')?;
+
+        self.open("subroutineBody");
+        self.expect_symbol('{')?;
+        let mut locals = Vec::new();
+        while self.peek_keyword("var") {
+            self.open("varDec");
+            self.advance();
+            let (ty, names) = self.parse_names()?;
+            locals.extend(names.into_iter().map(|name| (ty.clone(), name)));
+            self.expect_symbol(';')?;
+            self.close("varDec");
+        }
+        let statements = self.parse_statements()?;
+        self.expect_symbol('}')?;
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+        Ok(Subroutine { kind, return_type, name, parameters, locals, statements })
+    }
+
+    fn parse_statements(&mut self) -> Result<Vec<Statement>, AsmError> {
+        self.open("statements");
+        let mut statements = Vec::new();
+        loop {
+            let statement = match self.peek() {
+                Some(&TokenKind::Keyword("let")) => self.parse_let()?,
+                Some(&TokenKind::Keyword("if")) => self.parse_if()?,
+                Some(&TokenKind::Keyword("while")) => self.parse_while()?,
+                Some(&TokenKind::Keyword("do")) => {
+                    self.open("doStatement");
+                    self.advance();
+                    let call = self.parse_call()?;
+                    self.expect_symbol(';')?;
+                    self.close("doStatement");
+                    Statement::Do(call)
+                }
+                Some(&TokenKind::Keyword("return")) => {
+                    self.open("returnStatement");
+                    self.advance();
+                    let value = if self.peek_symbol(';') { None } else { Some(self.parse_expression()?) };
+                    self.expect_symbol(';')?;
+                    self.close("returnStatement");
+                    Statement::Return(value)
+                }
+                Some(&TokenKind::Symbol('}')) => break,
+                _ => return Err(self.error("a statement or '}'")),
+            };
+            statements.push(statement);
+        }
+        self.close("statements");
+        Ok(statements)
+    }
+
+    fn parse_let(&mut self) -> Result<Statement, AsmError> {
+        self.open("letStatement");
+        self.advance();
+        let (name, span) = self.identifier("a variable name")?;
+        let index = if self.peek_symbol('[') {
+            self.advance();
+            let index = self.parse_expression()?;
+            self.expect_symbol(']')?;
+            Some(index)
+        } else {
+            None
+        };
+        self.expect_symbol('=')?;
+        let value = self.parse_expression()?;
+        self.expect_symbol(';')?;
+        self.close("letStatement");
+        Ok(Statement::Let { name, span, index, value })
+    }
+
+    /// ( expression ) { statements }, the shared part of if and while
+    fn parse_condition_and_block(&mut self) -> Result<(Expression, Vec<Statement>), AsmError> {
+        self.expect_symbol('(')?;
+        let condition = self.parse_expression()?;
+        self.expect_symbol(')')?;
+        self.expect_symbol('{')?;
+        let block = self.parse_statements()?;
+        self.expect_symbol('}')?;
+        Ok((condition, block))
+    }
+
+    fn parse_if(&mut self) -> Result<Statement, AsmError> {
+        self.open("ifStatement");
+        self.advance();
+        let (condition, then) = self.parse_condition_and_block()?;
+        let otherwise = if self.peek_keyword("else") {
+            self.advance();
+            self.expect_symbol('{')?;
+            let otherwise = self.parse_statements()?;
+            self.expect_symbol('}')?;
+            Some(otherwise)
+        } else {
+            None
+        };
+        self.close("ifStatement");
+        Ok(Statement::If { condition, then, otherwise })
+    }
+
+    fn parse_while(&mut self) -> Result<Statement, AsmError> {
+        self.open("whileStatement");
+        self.advance();
+        let (condition, body) = self.parse_condition_and_block()?;
+        self.close("whileStatement");
+        Ok(Statement::While { condition, body })
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, AsmError> {
+        self.open("expression");
+        let first = Box::new(self.parse_term()?);
+        let mut rest = Vec::new();
+        while let Some(&TokenKind::Symbol(op)) = self.peek() {
+            if !"+-*/&|<>=".contains(op) {
+                break;
+            }
+            self.advance();
+            rest.push((op, self.parse_term()?));
+        }
+        self.close("expression");
+        Ok(Expression { first, rest })
+    }
+
+    fn parse_term(&mut self) -> Result<Term, AsmError> {
+        self.open("term");
+        let term = match self.peek().cloned() {
+            Some(TokenKind::Integer(value)) => {
+                self.advance();
+                Term::Integer(value)
+            }
+            Some(TokenKind::Str(text)) => {
+                self.advance();
+                Term::Str(text)
+            }
+            Some(TokenKind::Keyword(keyword)) => {
+                let constant = match keyword {
+                    "true" => KeywordConstant::True,
+                    "false" => KeywordConstant::False,
+                    "null" => KeywordConstant::Null,
+                    "this" => KeywordConstant::This,
+                    _ => return Err(self.error("an expression")),
+                };
+                self.advance();
+                Term::Keyword(constant)
+            }
+            Some(TokenKind::Symbol('(')) => {
+                self.advance();
+                let expression = self.parse_expression()?;
+                self.expect_symbol(')')?;
+                Term::Parenthesized(expression)
+            }
+            Some(TokenKind::Symbol(op)) if op == '-' || op == '~' => {
+                self.advance();
+                Term::Unary(op, Box::new(self.parse_term()?))
+            }
+            Some(TokenKind::Identifier(_)) => {
+                let next = self.tokens.get(self.position + 1).map(|token| &token.kind);
+                if next == Some(&TokenKind::Symbol('(')) || next == Some(&TokenKind::Symbol('.')) {
+                    Term::Call(self.parse_call()?)
+                } else {
+                    let (name, span) = self.identifier("a variable name")?;
+                    if self.peek_symbol('[') {
+                        self.advance();
+                        let index = self.parse_expression()?;
+                        self.expect_symbol(']')?;
+                        Term::Index(name, span, index)
+                    } else {
+                        Term::Var(name, span)
+                    }
+                }
+            }
+            _ => return Err(self.error("an expression")),
+        };
+        self.close("term");
+        Ok(term)
+    }
+
+    fn parse_call(&mut self) -> Result<SubroutineCall, AsmError> {
+        let (first, span) = self.identifier("a subroutine name")?;
+        let (receiver, name) = if self.peek_symbol('.') {
+            self.advance();
+            (Some(first), self.identifier("a subroutine name")?.0)
+        } else {
+            (None, first)
+        };
+        self.expect_symbol('(')?;
+        self.open("expressionList");
+        let mut arguments = Vec::new();
+        if !self.peek_symbol(')') {
+            arguments.push(self.parse_expression()?);
+            while self.peek_symbol(',') {
+                self.advance();
+                arguments.push(self.parse_expression()?);
+            }
+        }
+        self.close("expressionList");
+        self.expect_symbol(')')?;
+        Ok(SubroutineCall { receiver, name, span, arguments })
+    }
+}
+
+fn parser(source: &str, file_name: &str, xml: bool) -> Result<Parser, AsmError> {
+    Ok(Parser {
+        tokens: tokenize(source, file_name)?,
+        position: 0,
+        file_name: file_name.to_string(),
+        xml: if xml { Some(String::new()) } else { None },
+        depth: 0,
+    })
+}
+
+/// Parses a .jack file, which holds one class
+pub fn parse_class(source: &str, file_name: &str) -> Result<Class, AsmError> {
+    parser(source, file_name, false)?.parse_class()
+}
+
+/// Writes the parse tree of a .jack file in the course's XML format (Xxx.xml)
+pub fn parse_tree_xml(source: &str, file_name: &str) -> Result<String, AsmError> {
+    let mut parser = parser(source, file_name, true)?;
+    parser.parse_class()?;
+    Ok(parser.xml.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_a_class() {
+        let source = "class Main {\n  static int count;\n  field Point p, q;\n\
+                      function void main(int n) {\n    var Array a;\n    let a[n] = -(n + 2) * 3;\n\
+                      do Output.printInt(a[n]);\n    return;\n  }\n}\n";
+        let class = parse_class(source, "Main.jack").unwrap();
+        assert_eq!(class.name, "Main");
+        assert_eq!(class.vars[1], ClassVarDec {
+            kind: ClassVarKind::Field,
+            ty: Type::Class("Point".to_string()),
+            names: vec!["p".to_string(), "q".to_string()],
+        });
+        let main = &class.subroutines[0];
+        assert_eq!((main.kind, main.return_type.clone()), (SubroutineKind::Function, None));
+        assert_eq!(main.parameters, vec![(Type::Int, "n".to_string())]);
+        assert_eq!(main.statements.len(), 3);
+        match main.statements[0] {
+            Statement::Let { ref index, ref value, .. } => {
+                assert!(index.is_some());
+                assert!(matches!(*value.first, Term::Unary('-', _)));
+                assert_eq!(value.rest.len(), 1);
+            }
+            ref other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let message = |source: &str| parse_class(source, "Main.jack").unwrap_err().to_string();
+        assert_eq!(message("class Main { function void f() { let x = 1 } }"),
+                   "Main.jack:1:44: expected ';', found '}'");
+        assert_eq!(message("class Main { function void f() { return; }"),
+                   "Main.jack:1:43: expected a subroutine declaration or '}', found end of file");
+        assert_eq!(message("class Main { var int x; }"),
+                   "Main.jack:1:14: expected a subroutine declaration or '}', found 'var'");
+        assert_eq!(message("class Main { } class"), "Main.jack:1:16: expected end of file after the class, found 'class'");
+    }
+
+    #[test]
+    fn write_parse_tree() {
+        let xml = parse_tree_xml("class A { method void f() { do g(x < 1); return; } }", "A.jack").unwrap();
+        assert_eq!(xml, "\
+<class>
+  <keyword> class </keyword>
+  <identifier> A </identifier>
+  <symbol> { </symbol>
+  <subroutineDec>
+    <keyword> method </keyword>
+    <keyword> void </keyword>
+    <identifier> f </identifier>
+    <symbol> ( </symbol>
+    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <statements>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> g </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+            <expression>
+              <term>
+                <identifier> x </identifier>
+              </term>
+              <symbol> &lt; </symbol>
+              <term>
+                <integerConstant> 1 </integerConstant>
+              </term>
+            </expression>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+        <returnStatement>
+          <keyword> return </keyword>
+          <symbol> ; </symbol>
+        </returnStatement>
+      </statements>
+      <symbol> } </symbol>
+    </subroutineBody>
+  </subroutineDec>
+  <symbol> } </symbol>
+</class>
+");
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+
+use error::{AsmError, Span};
+
+/// The reserved words of Jack
+pub const KEYWORDS: &[&str] = &[
+    "class", "constructor", "function", "method", "field", "static", "var", "int", "char", "boolean",
+    "void", "true", "false", "null", "this", "let", "do", "if", "else", "while", "return",
+];
+
+/// The symbols of Jack, each a token of its own
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+/// The lexical elements of Jack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword(&'static str),
+    Symbol(char),
+    Integer(u16),
+    /// A string constant, without its quotes
+    Str(String),
+    Identifier(String),
+}
+
+impl TokenKind {
+    /// The element name used for the token in the course's XML files
+    pub fn xml_tag(&self) -> &'static str {
+        match *self {
+            TokenKind::Keyword(_) => "keyword",
+            TokenKind::Symbol(_) => "symbol",
+            TokenKind::Integer(_) => "integerConstant",
+            TokenKind::Str(_) => "stringConstant",
+            TokenKind::Identifier(_) => "identifier",
+        }
+    }
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TokenKind::Keyword(keyword) => f.write_str(keyword),
+            TokenKind::Symbol(symbol) => write!(f, "{}", symbol),
+            TokenKind::Integer(value) => write!(f, "{}", value),
+            TokenKind::Str(ref text) => f.write_str(text),
+            TokenKind::Identifier(ref name) => f.write_str(name),
+        }
+    }
+}
+
+/// A token and where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Escapes the characters that XML reserves
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Splits Jack source into tokens, skipping whitespace and comments
+///
+/// Arguments:
+///
+/// * source - the Jack source text
+/// * file_name - the name of the source, used in errors
+pub fn tokenize(source: &str, file_name: &str) -> Result<Vec<Token>, AsmError> {
+    let mut tokens = Vec::new();
+    let mut in_comment = false;
+    for (index, text) in source.lines().enumerate() {
+        let span = |start: usize, end: usize| Span::new(file_name, index + 1, start, end);
+        let mut start = 0;
+        while start < text.len() {
+            let rest = &text[start..];
+            if in_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        in_comment = false;
+                        start += end + 2;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            let c = rest.chars().next().unwrap();
+            if c.is_whitespace() {
+                start += c.len_utf8();
+                continue;
+            }
+            if rest.starts_with("//") {
+                break;
+            }
+            if rest.starts_with("/*") {
+                in_comment = true;
+                start += 2;
+                continue;
+            }
+            let (kind, len) = if SYMBOLS.contains(c) {
+                (TokenKind::Symbol(c), 1)
+            } else if c.is_ascii_digit() {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                match rest[..len].parse::<u16>() {
+                    Ok(value) if value <= 32767 => (TokenKind::Integer(value), len),
+                    _ => {
+                        return Err(AsmError::InvalidJack {
+                            message: format!("integer constant '{}' is larger than 32767", &rest[..len]),
+                            span: span(start, start + len),
+                        });
+                    }
+                }
+            } else if c == '"' {
+                match rest[1..].find('"') {
+                    Some(end) => (TokenKind::Str(rest[1..end + 1].to_string()), end + 2),
+                    None => {
+                        return Err(AsmError::InvalidJack {
+                            message: "unterminated string constant".to_string(),
+                            span: span(start, text.len()),
+                        });
+                    }
+                }
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                let word = &rest[..len];
+                match KEYWORDS.iter().find(|keyword| **keyword == word) {
+                    Some(keyword) => (TokenKind::Keyword(keyword), len),
+                    None => (TokenKind::Identifier(word.to_string()), len),
+                }
+            } else {
+                return Err(AsmError::InvalidJack {
+                    message: format!("unexpected character '{}'", c),
+                    span: span(start, start + c.len_utf8()),
+                });
+            };
+            tokens.push(Token { kind, span: span(start, start + len) });
+            start += len;
+        }
+    }
+    Ok(tokens)
+}
+
+/// Writes the tokens of Jack source in the course's token XML format (XxxT.xml)
+pub fn tokens_xml(source: &str, file_name: &str) -> Result<String, AsmError> {
+    let mut xml = String::from("<tokens>\n");
+    for token in tokenize(source, file_name)? {
+        let tag = token.kind.xml_tag();
+        xml.push_str(&format!("<{}> {} </{}>\n", tag, escape_xml(&token.kind.to_string()), tag));
+    }
+    xml.push_str("</tokens>\n");
+    Ok(xml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source, "Main.jack").unwrap().into_iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn tokenize_statement() {
+        assert_eq!(kinds("let x = x+1; // increment\n/** doc\n comment */ do Output.printString(\"a b\");"), vec![
+            TokenKind::Keyword("let"),
+            TokenKind::Identifier("x".to_string()),
+            TokenKind::Symbol('='),
+            TokenKind::Identifier("x".to_string()),
+            TokenKind::Symbol('+'),
+            TokenKind::Integer(1),
+            TokenKind::Symbol(';'),
+            TokenKind::Keyword("do"),
+            TokenKind::Identifier("Output".to_string()),
+            TokenKind::Symbol('.'),
+            TokenKind::Identifier("printString".to_string()),
+            TokenKind::Symbol('('),
+            TokenKind::Str("a b".to_string()),
+            TokenKind::Symbol(')'),
+            TokenKind::Symbol(';'),
+        ]);
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        let message = |source: &str| tokenize(source, "Main.jack").unwrap_err().to_string();
+        assert_eq!(message("let x = 32768;"), "Main.jack:1:9: integer constant '32768' is larger than 32767");
+        assert_eq!(message("let s = \"abc;"), "Main.jack:1:9: unterminated string constant");
+        assert_eq!(message("let x = #;"), "Main.jack:1:9: unexpected character '#'");
+    }
+
+    #[test]
+    fn token_xml() {
+        assert_eq!(tokens_xml("if (x < 1)", "Main.jack").unwrap(), "<tokens>\n<keyword> if </keyword>\n\
+            <symbol> ( </symbol>\n<identifier> x </identifier>\n<symbol> &lt; </symbol>\n\
+            <integerConstant> 1 </integerConstant>\n<symbol> ) </symbol>\n</tokens>\n");
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::BufRead;
+
+use error::{AsmError, Span};
+
+/// The Hack keycodes of keys that don't type a character
+const KEY_NAMES: &[(&str, u16)] = &[
+    ("none", 0),
+    ("space", 32),
+    ("newline", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("esc", 140),
+];
+
+/// Looks up a key by its keycode or name, such as 131, up or f1
+pub fn keycode(key: &str) -> Option<u16> {
+    if let Ok(code) = key.parse::<u16>() {
+        return Some(code);
+    }
+    let key = key.to_ascii_lowercase();
+    if let Some(&(_, code)) = KEY_NAMES.iter().find(|&&(name, _)| name == key) {
+        return Some(code);
+    }
+    // f1 to f12 are 141 to 152
+    match key.strip_prefix('f').and_then(|n| n.parse::<u16>().ok()) {
+        Some(n) if (1..=12).contains(&n) => Some(140 + n),
+        _ => None,
+    }
+}
+
+/// A script of key presses, replayed into the KBD register as a program runs
+///
+/// Each line is "cycle key": from that cycle on, the key is held down until the
+/// next line's key replaces it. A key of 0 or none releases the keyboard.
+///
+/// ```text
+/// // move the paddle left, then right
+/// 10000 left
+/// 12000 none
+/// 15000 132
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    /// The cycle each key is pressed on and its keycode, sorted by cycle
+    events: Vec<(u64, u16)>,
+}
+
+impl KeyScript {
+    pub fn new(mut events: Vec<(u64, u16)>) -> KeyScript {
+        events.sort_by_key(|&(cycle, _)| cycle);
+        KeyScript { events }
+    }
+
+    /// Reads a key script
+    ///
+    /// Arguments:
+    ///
+    /// * reader - the script
+    /// * file_name - the name of the script, used in errors
+    pub fn read<R: BufRead>(reader: R, file_name: &str) -> Result<KeyScript, AsmError> {
+        let mut events = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| AsmError::io(e, file_name))?;
+            let text = line.split("//").next().unwrap().trim();
+            if text.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            let event = match fields[..] {
+                [cycle, key] => cycle.parse::<u64>().ok().and_then(|cycle| keycode(key).map(|code| (cycle, code))),
+                _ => None,
+            };
+            match event {
+                Some(event) => events.push(event),
+                None => {
+                    let start = line.find(text).unwrap_or(0);
+                    return Err(AsmError::InvalidKeyEvent {
+                        text: text.to_string(),
+                        span: Span::new(file_name, index + 1, start, start + text.len()),
+                    });
+                }
+            }
+        }
+        Ok(KeyScript::new(events))
+    }
+
+    /// The key presses, sorted by cycle
+    pub fn events(&self) -> &[(u64, u16)] {
+        &self.events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keycodes() {
+        assert_eq!(keycode("131"), Some(131));
+        assert_eq!(keycode("Up"), Some(131));
+        assert_eq!(keycode("f12"), Some(152));
+        assert_eq!(keycode("f13"), None);
+        assert_eq!(keycode("shift"), None);
+    }
+
+    #[test]
+    fn read_script() {
+        let script = "// replay\n12000 none\n10000 left  // hold left\n\n15000 132\n";
+        let script = KeyScript::read(script.as_bytes(), "test.keys").unwrap();
+        assert_eq!(script.events(), &[(10000, 130), (12000, 0), (15000, 132)]);
+    }
+
+    #[test]
+    fn read_invalid_event() {
+        match KeyScript::read("10 up\nsoon up\n".as_bytes(), "test.keys") {
+            Err(AsmError::InvalidKeyEvent { text, span }) => {
+                assert_eq!(text, "soon up");
+                assert_eq!(span.line, 2);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
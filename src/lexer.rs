@@ -0,0 +1,123 @@
+/// Whether a character can be part of a symbol: a letter, a digit, '_', '.', '$' or ':'
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+/// Whether `name` can be used as a label or variable: letters, digits, '_',
+/// '.', '$' and ':', not starting with a digit
+pub fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(is_identifier_char)
+}
+
+/// The kinds of token on a line of Hack assembly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// '@', which starts an A instruction
+    At,
+    /// '(', which starts a label declaration
+    LeftParen,
+    /// ')', which ends a label declaration
+    RightParen,
+    /// '=', which ends the dest field of a C instruction
+    Equals,
+    /// ';', which starts the jump field of a C instruction
+    Semicolon,
+    /// Any other run of characters up to whitespace, punctuation or a comment,
+    /// such as a symbol, a constant or a mnemonic
+    Word,
+}
+
+/// A token and where it is on its line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    /// The text of the token, a slice of the line
+    pub text: &'a str,
+    /// The byte offset of the token in the line
+    pub start: usize,
+}
+
+impl<'a> Token<'a> {
+    /// The byte offset just past the token
+    pub fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+/// Splits a line of Hack assembly into tokens. Whitespace of any kind (spaces,
+/// tabs, a carriage return left by CRLF line endings) separates tokens, and a
+/// comment starting with "//" anywhere on the line is dropped.
+pub fn tokenize_line(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            _ if c.is_whitespace() => continue,
+            '/' if text[start..].starts_with("//") => break,
+            '@' => TokenKind::At,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '=' => TokenKind::Equals,
+            ';' => TokenKind::Semicolon,
+            // a character constant, which may quote whitespace or punctuation, as in ' ' or '('
+            '\'' if text[start + 1..].chars().nth(1) == Some('\'') => {
+                chars.next();
+                let end = chars.next().unwrap().0 + 1;
+                tokens.push(Token { kind: TokenKind::Word, text: &text[start..end], start });
+                continue;
+            }
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(index, next)) = chars.peek() {
+                    if next.is_whitespace() || "@()=;".contains(next) || text[index..].starts_with("//") {
+                        break;
+                    }
+                    end = index + next.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token { kind: TokenKind::Word, text: &text[start..end], start });
+                continue;
+            }
+        };
+        tokens.push(Token { kind, text: &text[start..start + c.len_utf8()], start });
+    }
+    tokens
+}
+
+/// The text of a line from the start of the first token to the end of the
+/// last, which is the code on the line without surrounding whitespace or comments
+pub fn code<'a>(text: &'a str, tokens: &[Token<'a>]) -> &'a str {
+    match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => &text[first.start..last.end()],
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds_and_texts(text: &str) -> Vec<(TokenKind, &str)> {
+        tokenize_line(text).into_iter().map(|token| (token.kind, token.text)).collect()
+    }
+
+    #[test]
+    fn tokenize_c_instruction() {
+        let expected = vec![(TokenKind::Word, "D"), (TokenKind::Equals, "="), (TokenKind::Word, "M"),
+                            (TokenKind::Semicolon, ";"), (TokenKind::Word, "JGT")];
+        assert_eq!(kinds_and_texts("D=M;JGT"), expected);
+        assert_eq!(kinds_and_texts("\t D = M ; JGT // jump\r"), expected);
+        assert_eq!(kinds_and_texts("D=M;JGT//jump"), expected);
+    }
+
+    #[test]
+    fn tokenize_label_and_a_instruction() {
+        assert_eq!(kinds_and_texts("  (LOOP)"), vec![(TokenKind::LeftParen, "("), (TokenKind::Word, "LOOP"),
+                                                     (TokenKind::RightParen, ")")]);
+        let tokens = tokenize_line("    @sum// total");
+        assert_eq!((tokens[1].text, tokens[1].start, tokens[1].end()), ("sum", 5, 8));
+        assert_eq!(code("    @sum// total", &tokens), "@sum");
+        assert!(tokenize_line("   // only a comment").is_empty());
+        assert_eq!(kinds_and_texts("@' ' // space"), vec![(TokenKind::At, "@"), (TokenKind::Word, "' '")]);
+    }
+}
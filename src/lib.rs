@@ -1,16 +1,57 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write, BufRead, Seek, SeekFrom};
+use std::io::{self, BufReader, BufRead};
 
-pub trait Decode {
-    /// Generates the binary representation of an instruction using its fields
-    /// 
-    /// Arguments:
-    /// 
-    /// * instruct_fields - fields of the instruction
-    /// * info_map - additional information for decoding, such as whether dest and jump were set
-    fn decode(&self, instruct_fields: Vec<&str>, info_map: &HashMap<&str, bool>) -> String; 
-} 
+mod diagnostics;
+mod disassembler;
+mod emulator;
+mod error;
+mod instruction;
+mod jack;
+mod keyboard;
+mod lexer;
+mod listing;
+mod output;
+mod render;
+mod screen;
+mod source_map;
+mod symbol_map;
+mod test_script;
+mod vm;
+
+pub use diagnostics::Diagnostics;
+pub use disassembler::{disassemble, disassemble_with_symbols, Disassembler, Word};
+pub use emulator::{Emulator, KBD, RAM_SIZE, ROM_SIZE, SCREEN};
+pub use error::{AsmError, SourceLine, Span};
+pub use instruction::{Comp, Dest, Instruction, Jump, Value};
+pub use jack::{compile_jack, parse_class, parse_tree_xml, tokenize, tokens_xml, Class, ClassVarDec, ClassVarKind,
+               Expression, KeywordConstant, Statement, Subroutine, SubroutineCall, SubroutineKind, Term, Token,
+               TokenKind, Type, KEYWORDS};
+pub use keyboard::{keycode, KeyScript};
+pub use lexer::{is_identifier, tokenize_line};
+pub use listing::write_listing;
+pub use output::{read_words, write_words, OutputFormat};
+pub use render::{Renderer, Sources};
+pub use screen::{write_screen, ImageFormat, SCREEN_HEIGHT, SCREEN_WIDTH, SCREEN_WORDS};
+pub use source_map::{SourceLocation, SourceMap, SourceMapFormat};
+pub use symbol_map::{symbols, write_symbols, Symbol, SymbolFormat, SymbolKind, SymbolMap};
+pub use test_script::{run_test_script, Execution, TestScript};
+pub use vm::{parse_vm_line, translate_vm, translate_vm_program, ArithmeticOp, Segment, Translation, VmCommand,
+             VmTranslator};
+
+/// The largest constant an A instruction can hold (15 bits)
+pub const MAX_ADDRESS: u16 = 0x7fff;
+
+/// The dialects of Hack assembly that the assembler accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// The assembly language of the course
+    #[default]
+    Standard,
+    /// The standard language, plus hexadecimal (@0x4000), binary (@0b101)
+    /// and printable ASCII character (@'A') constants
+    Extended,
+}
 
 pub struct ADecoder {}
 
@@ -18,412 +59,985 @@ impl ADecoder {
     pub fn new() -> ADecoder {
         ADecoder{}
     }
+
+    /// Generates the machine word of an A instruction
+    ///
+    /// Arguments:
+    ///
+    /// * value - the operand of the instruction, which must already be resolved to a constant
+    /// * line - the source line of the instruction, used to locate errors
+    pub fn decode(&self, value: &Value, line: &SourceLine) -> Result<u16, AsmError> {
+        // the op code is a 0 in the top bit, which every valid address leaves clear
+        let address = match *value {
+            Value::Constant(address) if address <= MAX_ADDRESS => address,
+            Value::Constant(address) => return Err(AsmError::AddressOutOfRange {
+                value: address.to_string(),
+                span: line.span(),
+            }),
+            Value::Symbol(ref symbol) => return Err(AsmError::UndefinedSymbol {
+                symbol: symbol.clone(),
+                span: line.span(),
+            }),
+        };
+        Ok(address)
+    }
 }
 
-impl Decode for ADecoder {
-    fn decode(&self, instruct_fields: Vec<&str>, info_map: &HashMap<&str, bool>) -> String {
-        let mut instruct_str = String::new();
-        instruct_str.push('0'); // push the op code
-        let address: i32 = (*(instruct_fields.get(0).unwrap())).parse::<i32>().unwrap();
-        instruct_str.push_str(format!("{:015b}", address).as_str()); // pad with zeros to make a width of 15 bits
-        instruct_str
+impl Default for ADecoder {
+    fn default() -> ADecoder {
+        ADecoder::new()
     }
 }
 
 pub struct CDecoder {
-    dest_map: HashMap<String, String>,
-    comp_map: HashMap<String, String>,
-    jump_map: HashMap<String, String>,
+    dest_map: HashMap<String, u16>,
+    comp_map: HashMap<String, u16>,
+    jump_map: HashMap<String, u16>,
 }
 
-impl CDecoder {
-    pub fn new(dest_file: File, comp_file: File, jump_file: File) -> CDecoder {
-        let mut buf_reader = BufReader::new(dest_file);
-        let mut dest_map: HashMap<String, String> = HashMap::new();
-        let mut comp_map: HashMap<String, String> = HashMap::new();
-        let mut jump_map: HashMap<String, String> = HashMap::new();
-
-        for line in buf_reader.lines() {
-            let unwrapped_line = line.unwrap(); // unwrapped_line is a String
-            let split_line: Vec<String> = unwrapped_line.split(" ").map(|s| s.to_string()).collect();
-            dest_map.insert((*split_line.get(0).unwrap()).clone(), (*split_line.get(1).unwrap()).clone());
-        }
+/// The Hack dest mnemonics and their binary encodings
+const DEST_TABLE: &str = include_str!("../dest_file.txt");
+/// The Hack comp mnemonics and their binary encodings (including the a bit)
+const COMP_TABLE: &str = include_str!("../comp_file.txt");
+/// The Hack jump mnemonics and their binary encodings
+const JUMP_TABLE: &str = include_str!("../jump_file.txt");
+/// The predefined Hack symbols other than R0-R15
+const PREDEFINED_SYMBOLS: &str = include_str!("../predefined_symbols.txt");
 
-        buf_reader = BufReader::new(comp_file);
-        for line in buf_reader.lines() {
-            let unwrapped_line = line.unwrap(); // unwrapped_line is a String
-            let split_line: Vec<String> = unwrapped_line.split(" ").map(|s| s.to_string()).collect();
-            comp_map.insert((*split_line.get(0).unwrap()).clone(), (*split_line.get(1).unwrap()).clone());
+/// Whether `name` is a predefined symbol: R0-R15, SP, LCL, ARG, THIS, THAT, SCREEN or KBD
+pub fn is_predefined(name: &str) -> bool {
+    let register = name.strip_prefix('R').and_then(|number| number.parse::<u8>().ok().filter(|n| n.to_string() == number));
+    register.is_some_and(|number| number < 16)
+        || PREDEFINED_SYMBOLS.lines().any(|line| line.split(' ').next() == Some(name))
+}
+
+/// Reads a table of "<mnemonic> <value>" lines into a HashMap
+///
+/// Arguments:
+///
+/// * reader - the table contents
+/// * name - the name of the table, used in errors
+fn read_table<R: BufRead>(reader: R, name: &str) -> Result<HashMap<String, String>, AsmError> {
+    let mut table = HashMap::new();
+    for (index, line) in reader.lines().enumerate() {
+        let unwrapped_line = line.map_err(|e| AsmError::io(e, name))?; // unwrapped_line is a String
+        let split_line: Vec<&str> = unwrapped_line.split(" ").collect();
+        if split_line.len() != 2 {
+            return Err(AsmError::Io {
+                error: io::Error::new(io::ErrorKind::InvalidData, "expected a '<mnemonic> <value>' line"),
+                span: Span::new(name, index + 1, 0, unwrapped_line.len()),
+            });
         }
+        table.insert(split_line[0].to_string(), split_line[1].to_string());
+    }
+    Ok(table)
+}
 
-        buf_reader = BufReader::new(jump_file);
-        for line in buf_reader.lines() {
-            let unwrapped_line = line.unwrap(); // unwrapped_line is a String
-            let split_line: Vec<String> = unwrapped_line.split(" ").map(|s| s.to_string()).collect();
-            jump_map.insert((*split_line.get(0).unwrap()).clone(), (*split_line.get(1).unwrap()).clone());
+/// Reads a table of "<mnemonic> <bits>" lines, where each value is
+/// a binary number of at most `width` bits
+fn read_bit_table<R: BufRead>(reader: R, name: &str, width: u32) -> Result<HashMap<String, u16>, AsmError> {
+    let mut table = HashMap::new();
+    for (mnemonic, bits) in read_table(reader, name)? {
+        match u16::from_str_radix(&bits, 2) {
+            Ok(value) if value < 1 << width => {
+                table.insert(mnemonic, value);
+            }
+            _ => return Err(AsmError::Io {
+                error: io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("'{}' is not a {}-bit binary value for '{}'", bits, width, mnemonic)),
+                span: Span::file(name),
+            }),
         }
+    }
+    Ok(table)
+}
+
+impl CDecoder {
+    /// Initializes a CDecoder from table files, for ISAs other than the
+    /// standard Hack one. Use CDecoder::default() for the built-in tables.
+    pub fn new(dest_file: File, comp_file: File, jump_file: File) -> Result<CDecoder, AsmError> {
+        Ok(CDecoder {
+            dest_map: read_bit_table(BufReader::new(dest_file), "dest table", 3)?,
+            comp_map: read_bit_table(BufReader::new(comp_file), "comp table", 7)?,
+            jump_map: read_bit_table(BufReader::new(jump_file), "jump table", 3)?,
+        })
+    }
+
+    /// Suggests valid mnemonics close to the one an error complains about
+    ///
+    /// Returns: a "did you mean" hint, if the error is about an unknown
+    /// mnemonic and there is a close enough match in the tables
+    pub fn suggest(&self, error: &AsmError) -> Option<String> {
+        let (word, table) = match *error {
+            AsmError::UnknownComp { ref comp, .. } => (comp, &self.comp_map),
+            AsmError::UnknownDest { ref dest, .. } => (dest, &self.dest_map),
+            AsmError::UnknownJump { ref jump, .. } => (jump, &self.jump_map),
+            _ => return None,
+        };
+        render::did_you_mean(&render::closest_matches(word, table.keys().map(|k| k.as_str())))
+    }
+}
+
+impl Default for CDecoder {
+    /// Initializes a CDecoder with the compiled-in Hack tables
+    fn default() -> CDecoder {
         CDecoder {
-            dest_map: dest_map,
-            comp_map: comp_map,
-            jump_map: jump_map
+            dest_map: read_bit_table(DEST_TABLE.as_bytes(), "dest table", 3).unwrap(),
+            comp_map: read_bit_table(COMP_TABLE.as_bytes(), "comp table", 7).unwrap(),
+            jump_map: read_bit_table(JUMP_TABLE.as_bytes(), "jump table", 3).unwrap(),
         }
     }
 }
 
-impl Decode for CDecoder {
-    fn decode(&self, instruct_fields: Vec<&str>, info_map: &HashMap<&str, bool>) -> String {
-        let mut instruct_str = String::new();
-        let mut comp_index = 0; // the index of the comp instruction in the vector
-        // binary forms of the 3 fields
-        let dest_bin: String;
-        let comp_bin: String;
-        let jump_bin: String;
-        // if dest is specified, it would be the first field
-        if *info_map.get("dest").unwrap() {
-            let dest = instruct_fields.get(0).unwrap().to_string();
-            dest_bin = self.dest_map.get(&dest).unwrap().to_string();
-            comp_index = 1;
-        } else {
-            dest_bin = "000".to_string();
-        }
-        let comp = instruct_fields.get(comp_index).unwrap().to_string();
-        comp_bin = self.comp_map.get(&comp).unwrap().clone().to_string();
-        // if jump is specified, it would be the last field
-        if *info_map.get("jump").unwrap() {
-            let jump = instruct_fields.get(instruct_fields.len() - 1).unwrap().to_string();
-            jump_bin = self.jump_map.get(&jump).unwrap().clone().to_string();
-        } else {
-            jump_bin = "000".to_string();
-        }
+impl CDecoder {
+    /// Generates the machine word of a C instruction
+    ///
+    /// Arguments:
+    ///
+    /// * dest, comp, jump - the fields of the instruction
+    /// * line - the source line of the instruction, used to locate errors
+    pub fn decode(&self, dest: Option<Dest>, comp: Comp, jump: Option<Jump>,
+                  line: &SourceLine) -> Result<u16, AsmError> {
+        // binary forms of the 3 fields, looked up in the tables in case they are custom
+        let dest_bits = match dest {
+            Some(dest) => *self.dest_map.get(dest.mnemonic()).ok_or_else(|| AsmError::UnknownDest {
+                dest: dest.to_string(),
+                span: line.span(),
+            })?,
+            None => 0,
+        };
+        let comp_bits = *self.comp_map.get(comp.mnemonic()).ok_or_else(|| AsmError::UnknownComp {
+            comp: comp.to_string(),
+            span: line.span(),
+        })?;
+        let jump_bits = match jump {
+            Some(jump) => *self.jump_map.get(jump.mnemonic()).ok_or_else(|| AsmError::UnknownJump {
+                jump: jump.to_string(),
+                span: line.span(),
+            })?,
+            None => 0,
+        };
+
+        // 111a cccc ccdd djjj
+        Ok(0b111 << 13 | comp_bits << 6 | dest_bits << 3 | jump_bits)
+    }
+}
+
+/// Generates the machine word of an instruction, using the
+/// decoder for its type
+///
+/// Returns: the machine word, or None for labels, which take up no ROM
+pub fn decode_instruction(instruction: &Instruction, a_decoder: &ADecoder, c_decoder: &CDecoder,
+                          line: &SourceLine) -> Result<Option<u16>, AsmError> {
+    match *instruction {
+        Instruction::A(ref value) => a_decoder.decode(value, line).map(Some),
+        Instruction::C { dest, comp, jump } => c_decoder.decode(dest, comp, jump, line).map(Some),
+        Instruction::Label(_) => Ok(None),
+    }
+}
+
+/// Assembles Hack assembly source into machine words, entirely in memory
+///
+/// Returns: the machine words, or every error found in the source
+pub fn assemble(source: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+    let mut diagnostics = Diagnostics::new();
+    let assembly = assemble_source(source, "<source>", &CDecoder::default(), &mut diagnostics);
+    if diagnostics.has_errors() {
+        Err(diagnostics.into_errors())
+    } else {
+        Ok(assembly.words)
+    }
+}
+
+/// The result of assembling a source file
+pub struct Assembly {
+    /// The machine words, one per instruction
+    pub words: Vec<u16>,
+    /// The instructions in ROM order, with symbols replaced by addresses
+    pub instructions: Vec<Instruction>,
+    /// The symbol table after both passes, which also records the
+    /// source line of each instruction
+    pub symbol_table: SymbolTable,
+}
 
-        instruct_str.push_str("111"); // add the op code
-        instruct_str.push_str(comp_bin.as_str());
-        instruct_str.push_str(dest_bin.as_str());
-        instruct_str.push_str(jump_bin.as_str());
+/// Assembles Hack assembly source into machine words, collecting errors
+///
+/// Arguments:
+///
+/// * source - the assembly source text
+/// * file_name - the name of the source, used in errors
+/// * c_decoder - the decoder for C instructions, which may use custom tables
+/// * diagnostics - collects errors in the source
+///
+/// Returns: the assembled program, which is incomplete if any errors were found
+pub fn assemble_source(source: &str, file_name: &str, c_decoder: &CDecoder,
+                       diagnostics: &mut Diagnostics) -> Assembly {
+    assemble_source_with_syntax(source, file_name, c_decoder, Syntax::Standard, diagnostics)
+}
 
-        instruct_str
+/// Assembles source written in the given dialect of Hack assembly, collecting
+/// errors. See assemble_source for the other arguments.
+pub fn assemble_source_with_syntax(source: &str, file_name: &str, c_decoder: &CDecoder, syntax: Syntax,
+                                   diagnostics: &mut Diagnostics) -> Assembly {
+    let a_decoder = ADecoder::new();
+    let mut symbol_table = SymbolTable::with_predefined();
+    symbol_table.syntax = syntax;
+    let instructions = symbol_table.resolve(source, file_name, diagnostics);
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut words = Vec::with_capacity(instructions.len());
+    for (instruction, line_number) in instructions.iter().zip(symbol_table.instruction_lines.iter()) {
+        let line = SourceLine::new(file_name, *line_number, source_lines[*line_number - 1]);
+        match decode_instruction(instruction, &a_decoder, c_decoder, &line) {
+            Ok(Some(word)) => words.push(word),
+            Ok(None) => {}
+            Err(e) => diagnostics.push(e),
+        }
+    }
+    Assembly {
+        words,
+        instructions,
+        symbol_table,
     }
 }
-/// Splits an instruction line into its fields
+
+/// Parses a line of assembly into an instruction
 /// # Arguments
-/// 
-/// * `line` - The instruction line as a string slice
-/// 
+///
+/// * `line` - The source line
+///
 /// # Returns
-/// 
-/// * (split_line, info_map) - the split line along with a HashMap 
-/// with additional information (whether dest and jump were set, A instruction or C instruction) 
-/// 
-pub fn parse_line<'a>(line: &'a str) -> (Vec<&'a str>, HashMap<&'static str, bool>) {
-    let mut split_line: Vec<&str>;
-    let mut dest = true; 
-    let mut jump = true;
-    let mut info_map = HashMap::new();
-
-    if line.starts_with('@') {
-        let trimmed_line = line.trim_left_matches("@");
-        split_line = trimmed_line.split(" ").collect();
-        if split_line.len() > 1 {
-            split_line.truncate(1);
+///
+/// * The instruction on the line, or None if the line is blank or only a comment
+///
+pub fn parse_line(line: &SourceLine) -> Result<Option<Instruction>, AsmError> {
+    parse_line_with_syntax(line, Syntax::Standard)
+}
+
+/// Parses a line of assembly written in the given dialect into an instruction
+///
+/// Returns: the instruction on the line, or None if the line is blank or only a comment
+pub fn parse_line_with_syntax(line: &SourceLine, syntax: Syntax) -> Result<Option<Instruction>, AsmError> {
+    use lexer::TokenKind as Kind;
+    let tokens = tokenize_line(line.text);
+    let code = lexer::code(line.text, &tokens);
+    let first = match tokens.first() {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+
+    if first.kind == Kind::LeftParen {
+        return parse_label(line, &tokens, code).map(Some);
+    }
+
+    if first.kind == Kind::At {
+        let operand = match tokens.get(1) {
+            Some(operand) if operand.kind == Kind::Word => operand,
+            Some(operand) => return Err(AsmError::InvalidSymbol {
+                symbol: operand.text.to_string(),
+                span: line.span_of(operand.text),
+            }),
+            None => return Err(AsmError::InvalidSymbol {
+                symbol: String::new(),
+                span: line.span_of(first.text),
+            }),
+        };
+        if let Some(extra) = tokens.get(2) {
+            let text = &line.text[extra.start..first.start + code.len()];
+            return Err(AsmError::UnexpectedText {
+                text: text.to_string(),
+                span: line.span_of(text),
+            });
         }
-        info_map.insert("a_instruction", true);
-    } else {
-        let mut max_c_fields = 3; // C instructions have a maximum of 3 fields, but dest and jump are optional
-        if !line.contains('=') {
-            max_c_fields -= 1;
-            dest = false; 
+        let value = operand.text;
+        if let Some(constant) = parse_constant(value, syntax, line) {
+            return constant.map(|address| Some(Instruction::A(Value::Constant(address))));
+        }
+        if !is_identifier(value) {
+            return Err(AsmError::InvalidSymbol {
+                symbol: value.to_string(),
+                span: line.span_of(value),
+            });
+        }
+        return Ok(Some(Instruction::A(Value::Symbol(value.to_string()))));
+    }
+
+    // C instructions have the form dest=comp;jump, but dest and jump are optional.
+    // Each field is the text between the separators, which may be spaced out, as in "D = D + M".
+    let field = |from: usize, to: usize| -> &str {
+        if from < to {
+            &line.text[tokens[from].start..tokens[to - 1].end()]
+        } else {
+            // an empty field, located just after the separator before it
+            let at = if from > 0 { tokens[from - 1].end() } else { first.start };
+            &line.text[at..at]
         }
-        if !line.contains(';') {
-            max_c_fields -= 1;
-            jump = false;
+    };
+    let equals = tokens.iter().position(|token| token.kind == Kind::Equals);
+    let comp_start = equals.map_or(0, |index| index + 1);
+    let semicolon = tokens[comp_start..].iter()
+        .position(|token| token.kind == Kind::Semicolon)
+        .map(|index| comp_start + index);
+    let dest_text = equals.map(|index| field(0, index));
+    let comp_text = field(comp_start, semicolon.unwrap_or(tokens.len()));
+    let jump_text = semicolon.map(|index| field(index + 1, tokens.len()));
+    let mnemonic = |text: &str| -> String { text.split_whitespace().collect() };
+
+    let dest = match dest_text {
+        Some(dest) => Some(Dest::from_registers(&mnemonic(dest)).ok_or_else(|| AsmError::UnknownDest {
+            dest: dest.to_string(),
+            span: line.span_of(dest),
+        })?),
+        None => None,
+    };
+    let comp = Comp::from_any_order(&mnemonic(comp_text)).ok_or_else(|| AsmError::UnknownComp {
+        comp: comp_text.to_string(),
+        span: line.span_of(comp_text),
+    })?;
+    let jump = match jump_text {
+        Some(jump) => Some(Jump::from_mnemonic(&mnemonic(jump)).ok_or_else(|| AsmError::UnknownJump {
+            jump: jump.to_string(),
+            span: line.span_of(jump),
+        })?),
+        None => None,
+    };
+    Ok(Some(Instruction::C { dest, comp, jump }))
+}
+
+/// Parses the operand of an A instruction if it is a constant, which is a
+/// decimal number, or in extended syntax a 0x hexadecimal number, a 0b binary
+/// number or a character in single quotes
+///
+/// Returns: None if the operand isn't a constant, and otherwise its value or
+/// why it isn't a valid address
+fn parse_constant(value: &str, syntax: Syntax, line: &SourceLine) -> Option<Result<u16, AsmError>> {
+    let in_radix = |digits: &str, radix: u32| {
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return None;
         }
-        info_map.insert("a_instruction", false);
-        info_map.insert("dest", dest);
-        info_map.insert("jump", jump);
-        split_line = line.split(|c| c == '=' || c == ';' || c == ' ').collect();
-        split_line.truncate(max_c_fields);
+        u32::from_str_radix(digits, radix).ok()
+    };
+    let char_literal = value.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\''));
+    let (number, extended) = if let Some(digits) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        (in_radix(digits, 16), true)
+    } else if let Some(digits) = value.strip_prefix("0b").or_else(|| value.strip_prefix("0B")) {
+        (in_radix(digits, 2), true)
+    } else if let Some(literal) = char_literal.filter(|literal| literal.chars().count() == 1) {
+        (literal.chars().next().map(|c| c as u32), true)
+    } else {
+        let digits = value.strip_prefix('-').unwrap_or(value);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        // negative numbers fail to parse, and are reported as out of range
+        (value.parse::<u32>().ok(), false)
+    };
+    if extended && syntax == Syntax::Standard {
+        return Some(Err(AsmError::ExtendedConstant {
+            value: value.to_string(),
+            span: line.span_of(value),
+        }));
+    }
+    // the only characters in the Hack character set are printable ASCII
+    if char_literal.is_some() && !number.is_some_and(|c| (0x20..=0x7e).contains(&c)) {
+        return Some(Err(AsmError::NonAsciiCharacter {
+            value: value.to_string(),
+            span: line.span_of(value),
+        }));
     }
-    (split_line, info_map)
+    Some(match number {
+        Some(address) if address <= MAX_ADDRESS as u32 => Ok(address as u16),
+        _ => Err(AsmError::AddressOutOfRange {
+            value: value.to_string(),
+            span: line.span_of(value),
+        }),
+    })
+}
+
+/// Parses a label declaration, which must be a symbol in parentheses with nothing after it
+///
+/// Arguments:
+///
+/// * line - the source line
+/// * tokens - the tokens of the line, the first of which is '('
+/// * code - the text of the line without surrounding whitespace or comments
+fn parse_label(line: &SourceLine, tokens: &[lexer::Token], code: &str) -> Result<Instruction, AsmError> {
+    let malformed = |reason: String| AsmError::MalformedLabel {
+        label: code.to_string(),
+        reason,
+        span: line.span_of(code),
+    };
+    let close = tokens.iter()
+        .position(|token| token.kind == lexer::TokenKind::RightParen)
+        .ok_or_else(|| malformed("missing ')'".to_string()))?;
+    let inner = line.text[tokens[0].end()..tokens[close].start].trim();
+    if inner.is_empty() {
+        return Err(malformed("the label is empty".to_string()));
+    }
+    if inner.contains(char::is_whitespace) {
+        return Err(malformed(format!("'{}' contains whitespace", inner)));
+    }
+    if let Some(extra) = tokens.get(close + 1) {
+        let rest = &line.text[extra.start..tokens[0].start + code.len()];
+        return Err(malformed(format!("unexpected '{}' after ')'", rest)));
+    }
+    if !is_identifier(inner) {
+        return Err(AsmError::InvalidSymbol {
+            symbol: inner.to_string(),
+            span: line.span_of(inner),
+        });
+    }
+    Ok(Instruction::Label(inner.to_string()))
 }
 
 pub struct SymbolTable {
-    pub symbol_map: HashMap<String, i32>
+    pub symbol_map: HashMap<String, i32>,
+    /// The labels declared in the source, in order of declaration
+    pub labels: Vec<String>,
+    /// The variables allocated for the source, in order of allocation
+    pub variables: Vec<String>,
+    /// The source line number of each instruction returned by resolve, in ROM order
+    pub instruction_lines: Vec<usize>,
+    /// The dialect of the source
+    pub syntax: Syntax,
+    /// Where each label was declared
+    pub label_spans: HashMap<String, Span>,
+    /// Warnings found by the passes, which resolve passes on to its Diagnostics
+    warnings: Vec<AsmError>,
+    /// Errors found by the variable pass that don't stop an instruction being
+    /// resolved, which resolve passes on to its Diagnostics
+    errors: Vec<AsmError>,
 }
 
 impl SymbolTable {
     /// Initializes a new SymbolTable using the file of
     /// predefined symbols
-    pub fn new(predef_file: File) -> SymbolTable {
-        let buf_reader = BufReader::new(predef_file);
+    pub fn new(predef_file: File) -> Result<SymbolTable, AsmError> {
+        SymbolTable::from_reader(BufReader::new(predef_file))
+    }
+
+    /// Initializes a new SymbolTable with the compiled-in
+    /// Hack predefined symbols
+    pub fn with_predefined() -> SymbolTable {
+        SymbolTable::from_reader(PREDEFINED_SYMBOLS.as_bytes()).unwrap()
+    }
+
+    fn from_reader<R: BufRead>(reader: R) -> Result<SymbolTable, AsmError> {
+        let name = "predefined symbols";
         let mut symbol_map = HashMap::new();
-        for line in buf_reader.lines() {
-            let split_line: Vec<String> = line.unwrap().split(" ").map(|s| s.to_string()).collect();
-            let symbol = (*(split_line.get(0).unwrap())).clone();
-            let num = split_line.get(1).unwrap().parse::<i32>().unwrap();
-            symbol_map.insert(symbol, num);
+        for (symbol, num) in read_table(reader, name)? {
+            let address = num.parse::<i32>().map_err(|_| AsmError::AddressOutOfRange {
+                value: num.clone(),
+                span: Span::file(name),
+            })?;
+            symbol_map.insert(symbol, address);
         }
         for num in 0..16 {
             let r_symbol_str = format!("R{}", num);
             symbol_map.insert(r_symbol_str, num);
         }
-        SymbolTable {
-            symbol_map: symbol_map
-        }
+        Ok(SymbolTable {
+            symbol_map,
+            labels: Vec::new(),
+            variables: Vec::new(),
+            instruction_lines: Vec::new(),
+            syntax: Syntax::Standard,
+            label_spans: HashMap::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        })
     }
 
-    /// Makes two passes through an assembly code file
-    /// and processes symbols
-    /// 
+    /// Makes two passes through assembly source and processes symbols,
+    /// without touching the filesystem
+    ///
     /// Arguments:
-    /// 
-    /// asm_file: the original assembly file before any processing
-    /// intm_file: the intermediate file with all symbols replaced, and white/comments lines removed
-    pub fn parse_file(&mut self, mut asm_file: File, mut intm_file: File) {
-        let buf_reader = BufReader::new(asm_file.try_clone().unwrap());
+    ///
+    /// source: the assembly source text
+    /// file_name: the name of the source, used in errors
+    /// diagnostics: collects errors in the source, so that both passes can continue past them
+    ///
+    /// Returns: the instructions with all symbols replaced by addresses, and labels,
+    /// blank lines and comments removed. The source line of each instruction is
+    /// recorded in instruction_lines.
+    pub fn resolve(&mut self, source: &str, file_name: &str, diagnostics: &mut Diagnostics) -> Vec<Instruction> {
         let mut line_num = 0;
         let mut next_mem = 16;
+        // the first instruction that doesn't fit in ROM
+        let mut overflow = None;
         // parse label symbols first
-        for line in buf_reader.lines() {
-            let unwrapped_line = line.unwrap();
-            if unwrapped_line.is_empty() {
+        for (index, text) in source.lines().enumerate() {
+            if text.is_empty() {
                 continue;
             }
-            line_num = self.parse_label_in_line(unwrapped_line.as_str(), line_num);
+            let source_line = SourceLine::new(file_name, index + 1, text);
+            let before = line_num;
+            match self.parse_label_in_line(&source_line, line_num) {
+                Ok(next_line_num) => line_num = next_line_num,
+                Err(e) => {
+                    // keep counting instructions that failed to parse, so later labels keep their addresses
+                    if !text.trim_start().starts_with('(') {
+                        line_num += 1;
+                    }
+                    diagnostics.push(e);
+                }
+            }
+            if before == ROM_SIZE as i32 && line_num > before {
+                overflow = Some(source_line.span());
+            }
+        }
+        if let Some(span) = overflow {
+            diagnostics.push(AsmError::ProgramTooLarge { instructions: line_num as usize, span });
+        }
+        for warning in self.warnings.drain(..) {
+            diagnostics.warn(warning);
         }
-        asm_file.seek(SeekFrom::Start(0)); // seek back to the beginning of the file
-        let buf_reader = BufReader::new(asm_file.try_clone().unwrap());
-        for line in buf_reader.lines() {
-            let unwrapped_line = line.unwrap();
-            if unwrapped_line.is_empty() {
+        let mut instructions = Vec::new();
+        self.instruction_lines.clear();
+        for (index, text) in source.lines().enumerate() {
+            if text.is_empty() {
                 continue;
             }
-            next_mem = self.parse_variable_in_line(unwrapped_line.as_str(), next_mem, intm_file.try_clone().unwrap())
+            let source_line = SourceLine::new(file_name, index + 1, text);
+            // parse errors were already reported by the label pass
+            if let Ok(next) = self.parse_variable_in_line(&source_line, next_mem, &mut instructions) {
+                next_mem = next;
+            }
+        }
+        for warning in self.warnings.drain(..) {
+            diagnostics.warn(warning);
         }
+        for error in self.errors.drain(..) {
+            diagnostics.push(error);
+        }
+        instructions
     }
     ///
     /// Parses the label symbols in a line of instruction 
     /// 
     /// Arguments:
     /// 
-    /// line: the line string 
+    /// line: the source line
     /// line_num: the current line number, used for processing label symbols
     /// 
     /// Returns: the mutated line_num 
-    fn parse_label_in_line(&mut self, line: &str, mut line_num: i32) -> i32 {
-        // Assume that instruction lines would not start with an empty space
-        if line.starts_with(|c| c == ' ' || c == '/') {
-            return line_num;
-        }
-        if line.starts_with('(') {
-            let split_line: Vec<&str> = line.split(|c| c == '(' || c ==')' || c == ' ').collect(); 
-            let label = split_line[1].to_string(); // The second token contains the symbol 
-            if !self.symbol_map.contains_key(&label) {
+    fn parse_label_in_line(&mut self, line: &SourceLine, mut line_num: i32) -> Result<i32, AsmError> {
+        match parse_line_with_syntax(line, self.syntax)? {
+            Some(Instruction::Label(label)) => {
+                let start = line.text.find('(').and_then(|paren| line.text[paren..].find(&label).map(|i| paren + i));
+                let start = start.unwrap_or(0);
+                let span = Span::new(line.file, line.number, start, start + label.len());
+                if let Some(first) = self.label_spans.get(&label) {
+                    return Err(AsmError::DuplicateLabel { label, span, first: first.clone() });
+                }
+                if self.symbol_map.contains_key(&label) {
+                    // the predefined symbol keeps its address
+                    self.warnings.push(AsmError::LabelRedefinesPredefined { label: label.clone(), span: span.clone() });
+                    self.label_spans.insert(label, span);
+                    return Ok(line_num);
+                }
+                self.labels.push(label.clone());
+                self.label_spans.insert(label.clone(), span);
                 self.symbol_map.insert(label, line_num); // consume the label
             }
-            return line_num;
-        } 
-        line_num += 1;
-        line_num 
+            Some(_) => line_num += 1,
+            None => {}
+        }
+        Ok(line_num)
     }
     ///
     /// Parses variable symbols in a line of instruction
     /// 
     /// Arguments:
     /// 
-    /// line: the line literal
+    /// line: the source line
     /// next_mem: the next available memory location
-    /// intm_file: an intermediate file with symbols replaced and blank/comment lines removed
+    /// instructions: the instructions so far, with symbols replaced
     /// 
     /// Returns: the mutated next available memory location
-    fn parse_variable_in_line(&mut self, line: &str, mut next_mem: i32, mut intm_file: File) -> i32 {
-        // Assume that instruction lines would not start with an empty space
-        if line.starts_with(|c: char| c == ' ' || c == '/' || c == '(') {
-            return next_mem;
-        }
-        let mut writer = BufWriter::new(intm_file);
-        if line.starts_with('@') {
-            let split_line: Vec<&str> = line.split(|c| c == '@' || c == ' ').collect();
-            let variable = split_line[1].to_string(); // second token contains the variable
-            let var_clone = variable.clone();
-            if !variable.parse::<i32>().is_ok() { // if the variable isn't a number (i.e. setting an address)
+    fn parse_variable_in_line(&mut self, line: &SourceLine, mut next_mem: i32,
+                              instructions: &mut Vec<Instruction>) -> Result<i32, AsmError> {
+        let instruction = match parse_line_with_syntax(line, self.syntax)? {
+            None | Some(Instruction::Label(_)) => return Ok(next_mem),
+            Some(Instruction::A(Value::Symbol(variable))) => {
                 if !self.symbol_map.contains_key(&variable) {
-                    self.symbol_map.insert(variable, next_mem); // consume the variable
+                    let start = line.text.find('@').and_then(|at| line.text[at..].find(&variable).map(|i| at + i));
+                    let start = start.unwrap_or(0);
+                    let span = Span::new(line.file, line.number, start, start + variable.len());
+                    if let Some(label) = self.labels.iter().find(|label| label.eq_ignore_ascii_case(&variable)) {
+                        self.warnings.push(AsmError::VariableLikeLabel {
+                            variable: variable.clone(),
+                            label: label.clone(),
+                            span: span.clone(),
+                            label_line: self.label_spans[label].line,
+                        });
+                    }
+                    if next_mem >= SCREEN as i32 {
+                        self.errors.push(AsmError::OutOfVariableMemory {
+                            variable: variable.clone(),
+                            address: next_mem as usize,
+                            span,
+                        });
+                    }
+                    self.symbol_map.insert(variable.clone(), next_mem); // consume the variable
+                    self.variables.push(variable.clone());
                     next_mem += 1;
-                    // write to the intermediate file with the symbol replced
                 }
-                writer.write(format!("@{}\n", self.symbol_map.get(&var_clone).unwrap()).as_bytes());
-            } else {
-                let mut line_str = line.to_string();
-                line_str.push('\n');
-                writer.write(line_str.as_bytes());
+                // replace the symbol with its address
+                Instruction::A(Value::Constant(self.symbol_map[&variable] as u16))
             }
-        } else {
-            // write the C instruction as is to the intermediate file
-            let mut line_str = line.to_string();
-            line_str.push('\n');
-            writer.write(line_str.as_bytes());
-        }
-        next_mem
+            Some(instruction) => instruction,
+        };
+        instructions.push(instruction);
+        self.instruction_lines.push(line.number);
+        Ok(next_mem)
     }
 }
 
 #[cfg(test)]
+// the symbol table tests from the original suite are kept as they were written
+#[allow(unused_mut, clippy::bool_assert_comparison, clippy::unnecessary_to_owned)]
 mod tests {
     use super::*;
 
+    /// a source line for tests that don't check error locations
+    fn test_line(text: &str) -> SourceLine<'_> {
+        SourceLine::new("test.asm", 1, text)
+    }
+
+    /// parses a line that is expected to hold an instruction
+    fn parse(text: &str) -> Instruction {
+        parse_line(&test_line(text)).unwrap().unwrap()
+    }
+
+    fn c_instruction(dest: Option<Dest>, comp: Comp, jump: Option<Jump>) -> Instruction {
+        Instruction::C { dest, comp, jump }
+    }
+
     #[test]
     fn parse_a_instruction() {
-        let (parsed_line, info_map) = parse_line("@100");
-        println!("{:?}", parsed_line);
-        assert_eq!(parsed_line, vec!["100"]);
-        assert_eq!(*info_map.get("a_instruction").unwrap(), true);
+        assert_eq!(parse("@100"), Instruction::A(Value::Constant(100)));
     }
 
     #[test]
     fn parse_a_instruction_with_comment() {
+        assert_eq!(parse("@100 // set a register to 100"), Instruction::A(Value::Constant(100)));
+    }
 
-        let (parsed_line, info_map) = parse_line("@100 // set a register to 100");
-        println!("{:?}", parsed_line);
-        assert_eq!(parsed_line, vec!["100"]);
-        assert_eq!(*info_map.get("a_instruction").unwrap(), true);
+    #[test]
+    fn parse_a_instruction_symbol() {
+        assert_eq!(parse("@sum"), Instruction::A(Value::Symbol("sum".to_string())));
     }
 
     #[test]
     fn parse_c_instruction() {
-        let (parsed_line, info_map) = parse_line("D=D+M;JMP");
-        assert_eq!(parsed_line, vec!["D", "D+M", "JMP"]);
-        assert_eq!(*info_map.get("a_instruction").unwrap(), false);
-        assert_eq!(*info_map.get("dest").unwrap(), true);
-        assert_eq!(*info_map.get("jump").unwrap(), true);
+        assert_eq!(parse("D=D+M;JMP"), c_instruction(Some(Dest::D), Comp::DPlusM, Some(Jump::JMP)));
     }
 
 
     #[test]
     fn parse_c_instruction_with_comments() {
-        let (parsed_line, info_map) = parse_line("D=D+M;JMP // unconditional jump");
-        assert_eq!(parsed_line, vec!["D", "D+M", "JMP"]);
-        assert_eq!(*info_map.get("a_instruction").unwrap(), false);
-        assert_eq!(*info_map.get("dest").unwrap(), true);
-        assert_eq!(*info_map.get("jump").unwrap(), true);
+        assert_eq!(parse("D=D+M;JMP // unconditional jump"),
+                   c_instruction(Some(Dest::D), Comp::DPlusM, Some(Jump::JMP)));
     }
 
     #[test]
     fn parse_c_instruction_comp_only() {
-        let (parsed_line, info_map) = parse_line("D+M");
-        assert_eq!(parsed_line, vec!["D+M"]);
-        assert_eq!(*info_map.get("a_instruction").unwrap(), false);
-        assert_eq!(*info_map.get("dest").unwrap(), false);
-        assert_eq!(*info_map.get("jump").unwrap(), false);
+        assert_eq!(parse("D+M"), c_instruction(None, Comp::DPlusM, None));
     }
 
     #[test]
     fn parse_c_instruction_comp_and_dest_only() {
-        let (parsed_line, info_map) = parse_line("D=D+M");
-        assert_eq!(parsed_line, vec!["D", "D+M"]);
-        assert_eq!(*info_map.get("a_instruction").unwrap(), false);
-        assert_eq!(*info_map.get("dest").unwrap(), true);
-        assert_eq!(*info_map.get("jump").unwrap(), false);
+        assert_eq!(parse("D=D+M"), c_instruction(Some(Dest::D), Comp::DPlusM, None));
     }
 
     #[test]
     fn parse_c_instruction_comp_and_jump_only() {
-        let (parsed_line, info_map) = parse_line("D+M;JEQ");
-        assert_eq!(parsed_line, vec!["D+M", "JEQ"]);
-        assert_eq!(*info_map.get("a_instruction").unwrap(), false);
-        assert_eq!(*info_map.get("dest").unwrap(), false);
-        assert_eq!(*info_map.get("jump").unwrap(), true);
+        assert_eq!(parse("D+M;JEQ"), c_instruction(None, Comp::DPlusM, Some(Jump::JEQ)));
+    }
+
+    #[test]
+    fn parse_spaced_out_c_instruction() {
+        let expected = c_instruction(Some(Dest::D), Comp::DPlusM, Some(Jump::JMP));
+        assert_eq!(parse("D = D + M ; JMP"), expected);
+        assert_eq!(parse("\tD=D+M;JMP//no space before the comment\r"), expected);
+    }
+
+    #[test]
+    fn parse_alternate_spellings() {
+        assert_eq!(parse("DM=A+D"), c_instruction(Some(Dest::MD), Comp::DPlusA, None));
+        assert_eq!(parse("MAD = M | D ; JNE"), c_instruction(Some(Dest::AMD), Comp::DOrM, Some(Jump::JNE)));
+        assert_eq!(assemble("MA=M+D\nDA=A&D\n").unwrap(), assemble("AM=D+M\nAD=D&A\n").unwrap());
+    }
+
+    #[test]
+    fn parse_indented_lines() {
+        assert_eq!(parse("    @sum"), Instruction::A(Value::Symbol("sum".to_string())));
+        assert_eq!(parse("\t(LOOP)  // loop start"), Instruction::Label("LOOP".to_string()));
+        assert_eq!(parse_line(&test_line("  \t// comment")).unwrap(), None);
+        match parse_line(&test_line("    D=D+X")) {
+            Err(AsmError::UnknownComp { comp, span }) => {
+                assert_eq!(comp, "D+X");
+                assert_eq!((span.start, span.end), (6, 9));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_label() {
+        assert_eq!(parse("(LOOP) // loop start"), Instruction::Label("LOOP".to_string()));
+    }
+
+    #[test]
+    fn parse_blank_and_comment_lines() {
+        assert_eq!(parse_line(&test_line("")).unwrap(), None);
+        assert_eq!(parse_line(&test_line("// comment")).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_unknown_comp() {
+        let line = test_line("D=D+X;JMP");
+        match parse_line(&line) {
+            Err(AsmError::UnknownComp { comp, span }) => {
+                assert_eq!(comp, "D+X");
+                assert_eq!(span, Span::new("test.asm", 1, 2, 5));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_unknown_dest_and_jump() {
+        assert!(matches!(parse_line(&test_line("MM=1")), Err(AsmError::UnknownDest { .. })));
+        assert!(matches!(parse_line(&test_line("0;JMPP")), Err(AsmError::UnknownJump { .. })));
+    }
+
+    #[test]
+    fn parse_address_out_of_range() {
+        let line = test_line("@40000");
+        match parse_line(&line) {
+            Err(AsmError::AddressOutOfRange { value, span }) => {
+                assert_eq!(value, "40000");
+                assert_eq!((span.start, span.end), (1, 6));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(parse_line(&test_line("@-1")), Err(AsmError::AddressOutOfRange { .. })));
+    }
+
+    #[test]
+    fn parse_extended_constants() {
+        let parse_extended = |text: &str| parse_line_with_syntax(&test_line(text), Syntax::Extended);
+        let constant = |value| Ok(Some(Instruction::A(Value::Constant(value))));
+        assert_eq!(parse_extended("@0x4000").map_err(|e| e.to_string()), constant(0x4000));
+        assert_eq!(parse_extended("@0b101").map_err(|e| e.to_string()), constant(5));
+        assert_eq!(parse_extended("@'A'").map_err(|e| e.to_string()), constant(65));
+        assert_eq!(parse_extended("@' ' // space").map_err(|e| e.to_string()), constant(32));
+        assert_eq!(parse_extended("@100").map_err(|e| e.to_string()), constant(100));
+        for text in ["@0x8000", "@0b", "@0xG", "@-1"].iter() {
+            assert!(matches!(parse_extended(text), Err(AsmError::AddressOutOfRange { .. })), "{}", text);
+        }
+        // standard syntax rejects them
+        match parse_line(&test_line("@0x4000")) {
+            Err(error) => assert_eq!(error.to_string(), "test.asm:1:2: '0x4000' is only a constant in extended syntax"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(parse_line(&test_line("@'A'")), Err(AsmError::ExtendedConstant { .. })));
+        // only printable ASCII characters are constants
+        for text in ["@'é'", "@'\t'"].iter() {
+            match parse_extended(text) {
+                Err(error @ AsmError::NonAsciiCharacter { .. }) => {
+                    let message = format!("character constant {} is not a printable ASCII character", &text[1..]);
+                    assert_eq!(error.to_string(), format!("test.asm:1:2: {}", message));
+                }
+                other => panic!("unexpected result {:?} for {}", other, text),
+            }
+        }
+    }
+
+    #[test]
+    fn assemble_extended_syntax() {
+        let mut diagnostics = Diagnostics::new();
+        let source = "@0x4000\nD=A\n@'A'\n";
+        let assembly = assemble_source_with_syntax(source, "test.asm", &CDecoder::default(), Syntax::Extended,
+                                                   &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        assert_eq!(assembly.words, vec![0x4000, 0xec10, 65]);
+        assert!(assemble(source).is_err());
     }
 
     #[test]
     fn a_decode_test() {
         let decoder = ADecoder::new();
-        assert_eq!(&decoder.decode(vec!["4"], &HashMap::new()), "0000000000000100");
+        assert_eq!(decoder.decode(&Value::Constant(4), &test_line("")).unwrap(), 0b0000000000000100);
     }
 
     #[test]
     fn a_decode_test_2() {
         let decoder = ADecoder::new();
-        assert_eq!(&decoder.decode(vec!["100"], &HashMap::new()), "0000000001100100");
+        assert_eq!(decoder.decode(&Value::Constant(100), &test_line("")).unwrap(), 0b0000000001100100);
+    }
+
+    #[test]
+    fn a_decode_out_of_range() {
+        let decoder = ADecoder::new();
+        let result = decoder.decode(&Value::Constant(40000), &test_line("@40000"));
+        assert!(matches!(result, Err(AsmError::AddressOutOfRange { .. })));
+    }
+
+    #[test]
+    fn a_decode_unresolved_symbol() {
+        let decoder = ADecoder::new();
+        let result = decoder.decode(&Value::Symbol("sum".to_string()), &test_line("@sum"));
+        assert!(matches!(result, Err(AsmError::UndefinedSymbol { .. })));
     }
 
     /// setup function for CDecoder
     fn c_decoder_setup() -> CDecoder {
-        let dest_file = File::open("dest_file.txt").unwrap();
-        let comp_file = File::open("comp_file.txt").unwrap();
-        let jump_file = File::open("jump_file.txt").unwrap();
+        CDecoder::default()
+    }
+
+    /// opens a file relative to the crate root, regardless of the working directory
+    fn open_in_crate(name: &str) -> File {
+        File::open(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    /// resolves the symbols in a test file from the crate root
+    fn resolve_file(symbol_table: &mut SymbolTable, name: &str, diagnostics: &mut Diagnostics) -> Vec<Instruction> {
+        let source = std::fs::read_to_string(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+        symbol_table.resolve(&source, name, diagnostics)
+    }
 
-        CDecoder::new(dest_file, comp_file, jump_file)
+    #[test]
+    fn c_decoder_from_files() {
+        let decoder = CDecoder::new(open_in_crate("dest_file.txt"),
+                                    open_in_crate("comp_file.txt"),
+                                    open_in_crate("jump_file.txt")).unwrap();
+        let default_decoder = CDecoder::default();
+        assert_eq!(decoder.dest_map, default_decoder.dest_map);
+        assert_eq!(decoder.comp_map, default_decoder.comp_map);
+        assert_eq!(decoder.jump_map, default_decoder.jump_map);
     }
 
     #[test]
     fn c_decode_no_jump() {
         let decoder = c_decoder_setup();
-        let mut info_map = HashMap::new();
-        info_map.insert("dest", true);
-        info_map.insert("jump", false);
-        assert_eq!(&decoder.decode(vec!["MD", "D+1"], &info_map), "1110011111011000");
+        let instruction = decoder.decode(Some(Dest::MD), Comp::DPlusOne, None, &test_line("")).unwrap();
+        assert_eq!(instruction, 0b1110011111011000);
     }
 
     #[test]
     fn c_decode_no_jump_and_no_dest() {
         let decoder = c_decoder_setup();
-        let mut info_map = HashMap::new();
-        info_map.insert("dest", false);
-        info_map.insert("jump", false);
-        assert_eq!(&decoder.decode(vec!["D+1"], &info_map), "1110011111000000");
+        let instruction = decoder.decode(None, Comp::DPlusOne, None, &test_line("")).unwrap();
+        assert_eq!(instruction, 0b1110011111000000);
     }
 
     #[test]
     fn c_decode_no_dest() {
         let decoder = c_decoder_setup();
-        let mut info_map = HashMap::new();
-        info_map.insert("dest", false);
-        info_map.insert("jump", true);
-        assert_eq!(&decoder.decode(vec!["D+1", "JLE"], &info_map), "1110011111000110");
+        let instruction = decoder.decode(None, Comp::DPlusOne, Some(Jump::JLE), &test_line("")).unwrap();
+        assert_eq!(instruction, 0b1110011111000110);
     }
 
     #[test]
     fn c_decode_m_not_a() {
         let decoder = c_decoder_setup();
-        let mut info_map = HashMap::new();
-        info_map.insert("dest", true);
-        info_map.insert("jump", true);
-        assert_eq!(&decoder.decode(vec!["M", "M+1", "JEQ"], &info_map), "1111110111001010");
+        let instruction = decoder.decode(Some(Dest::M), Comp::MPlusOne, Some(Jump::JEQ), &test_line("")).unwrap();
+        assert_eq!(instruction, 0b1111110111001010);
     }
 
     #[test]
     fn c_decode_unconditional_jump() {
         let decoder = c_decoder_setup();
-        let mut info_map = HashMap::new();
-        info_map.insert("dest", false);
-        info_map.insert("jump", true);
-        assert_eq!(&decoder.decode(vec!["0", "JMP"], &info_map), "1110101010000111");
+        let instruction = decoder.decode(None, Comp::Zero, Some(Jump::JMP), &test_line("")).unwrap();
+        assert_eq!(instruction, 0b1110101010000111);
+    }
+
+    #[test]
+    fn c_decode_missing_from_table() {
+        let mut decoder = c_decoder_setup();
+        decoder.comp_map.remove("D+1");
+        let result = decoder.decode(None, Comp::DPlusOne, None, &test_line("D+1"));
+        assert!(matches!(result, Err(AsmError::UnknownComp { .. })));
+    }
+
+    #[test]
+    fn decode_instructions() {
+        let a_decoder = ADecoder::new();
+        let c_decoder = c_decoder_setup();
+        let line = test_line("");
+        let decode = |instruction| decode_instruction(&instruction, &a_decoder, &c_decoder, &line).unwrap();
+        assert_eq!(decode(parse("@4")), Some(0b0000000000000100));
+        assert_eq!(decode(parse("0;JMP")), Some(0b1110101010000111));
+        assert_eq!(decode(parse("(END)")), None);
     }
 
     fn symbol_table_setup() -> SymbolTable {
-        let file = File::open("predefined_symbols.txt").unwrap();
-        SymbolTable::new(file)
+        SymbolTable::with_predefined()
+    }
+
+    #[test]
+    fn symbol_table_from_file() {
+        let symbol_table = SymbolTable::new(open_in_crate("predefined_symbols.txt")).unwrap();
+        assert_eq!(symbol_table.symbol_map, SymbolTable::with_predefined().symbol_map);
     }
 
     #[test]
     fn test_label_parsing() {
         let mut symbol_table = symbol_table_setup();
-        symbol_table.parse_label_in_line("(END)", 10);
+        symbol_table.parse_label_in_line(&test_line("(END)"), 10).unwrap();
         assert_eq!(*symbol_table.symbol_map.get(&"END".to_string()).unwrap(), 10);
     }
 
+    #[test]
+    fn test_malformed_label() {
+        let mut symbol_table = symbol_table_setup();
+        for text in ["(END", "()", "(END // missing paren"].iter() {
+            let result = symbol_table.parse_label_in_line(&test_line(text), 10);
+            assert!(matches!(result, Err(AsmError::MalformedLabel { .. })), "{}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_label_reasons() {
+        let message = |text: &str| parse_line(&test_line(text)).unwrap_err().to_string();
+        assert_eq!(message("(END // missing paren"), "test.asm:1:1: malformed label '(END': missing ')'");
+        assert_eq!(message("(  )"), "test.asm:1:1: malformed label '(  )': the label is empty");
+        assert_eq!(message("(FOO BAR)"), "test.asm:1:1: malformed label '(FOO BAR)': 'FOO BAR' contains whitespace");
+        assert_eq!(message("  (END) @END"), "test.asm:1:3: malformed label '(END) @END': unexpected '@END' after ')'");
+        assert_eq!(message("(1ST)"), "test.asm:1:2: symbol '1ST' starts with a digit");
+        assert_eq!(message("(A=B)"), "test.asm:1:2: symbol 'A=B' contains '=', but symbols can only have letters, \
+                                      digits, '_', '.', '$' and ':'");
+        assert_eq!(parse("(sys.init$ret:0_a)"), Instruction::Label("sys.init$ret:0_a".to_string()));
+    }
+
+    #[test]
+    fn invalid_a_instruction_symbols() {
+        let message = |text: &str| parse_line(&test_line(text)).unwrap_err().to_string();
+        assert_eq!(message("@1abc"), "test.asm:1:2: symbol '1abc' starts with a digit");
+        assert_eq!(message("@"), "test.asm:1:1: '@' needs a symbol or a constant after it");
+        assert_eq!(message("@ // nothing"), "test.asm:1:1: '@' needs a symbol or a constant after it");
+        assert!(message("@x#y").starts_with("test.asm:1:2: symbol 'x#y' contains '#'"));
+        assert!(message("@(LOOP)").starts_with("test.asm:1:2: symbol '(' contains '('"));
+        assert_eq!(message("@sum total"), "test.asm:1:6: unexpected 'total' after the instruction");
+        assert_eq!(message("@5 6"), "test.asm:1:4: unexpected '6' after the instruction");
+    }
+
     #[test]
     fn test_variable_parsing() {
         let mut symbol_table = symbol_table_setup();
-        symbol_table.parse_variable_in_line("@start // start var", 10, File::create("blah").unwrap());
+        symbol_table.parse_variable_in_line(&test_line("@start // start var"), 10, &mut Vec::new()).unwrap();
         assert_eq!(*symbol_table.symbol_map.get(&"start".to_string()).unwrap(), 10);
     }
 
     #[test]
     fn test_non_variable_parsing() {
         let mut symbol_table = symbol_table_setup();
-        symbol_table.parse_variable_in_line("@10 // start var", 10, File::create("blah").unwrap());
+        let mut instructions = Vec::new();
+        symbol_table.parse_variable_in_line(&test_line("@10 // start var"), 10, &mut instructions).unwrap();
         assert_eq!(symbol_table.symbol_map.contains_key(&"10".to_string()), false);
+        assert_eq!(instructions, vec![Instruction::A(Value::Constant(10))]);
     }
 
     #[test]
@@ -436,9 +1050,7 @@ mod tests {
     #[test]
     fn test_file_parsing() {
         let mut symbol_table = symbol_table_setup();
-        let mut asm_file = File::open("symbol_test.txt").unwrap();
-        let mut intm_file = File::create("intm1.txt").unwrap();
-        symbol_table.parse_file(asm_file, intm_file);
+        resolve_file(&mut symbol_table, "symbol_test.txt", &mut Diagnostics::new());
         assert_eq!(*symbol_table.symbol_map.get(&"sum".to_string()).unwrap(), 16);
         assert_eq!(*symbol_table.symbol_map.get(&"HELLO".to_string()).unwrap(), 1);
         assert_eq!(*symbol_table.symbol_map.get(&"i".to_string()).unwrap(), 17);
@@ -450,24 +1062,162 @@ mod tests {
     #[test]
     fn test_file_parsing_2() {
         let mut symbol_table = symbol_table_setup();
-        let mut asm_file = File::open("symbol_test_2.txt").unwrap();
-        let mut intm_file = File::create("intm2.txt").unwrap();
-        symbol_table.parse_file(asm_file, intm_file);
+        let instructions = resolve_file(&mut symbol_table, "symbol_test_2.txt", &mut Diagnostics::new());
         assert_eq!(*symbol_table.symbol_map.get(&"sum".to_string()).unwrap(), 17);
         assert_eq!(*symbol_table.symbol_map.get(&"LOOP".to_string()).unwrap(), 4);
         assert_eq!(*symbol_table.symbol_map.get(&"i".to_string()).unwrap(), 16);
         assert_eq!(*symbol_table.symbol_map.get(&"STOP".to_string()).unwrap(), 8);
         assert_eq!(*symbol_table.symbol_map.get(&"R0".to_string()).unwrap(), 0);
         assert_eq!(*symbol_table.symbol_map.get(&"END".to_string()).unwrap(), 11);
+        assert_eq!(symbol_table.instruction_lines, vec![1, 2, 3, 4, 7, 8, 9, 10, 13, 14, 15, 18, 19]);
+        assert_eq!(symbol_table.labels, vec!["LOOP", "STOP", "END"]);
+        assert_eq!(symbol_table.variables, vec!["i", "sum"]);
+        assert_eq!(instructions.iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+                   vec!["@16", "M=1", "@17", "M=0", "@0", "D=D-M", "@16", "D=M", "@4", "D=D+M", "@17", "@16", "@11"]);
     }
 
     #[test]
     fn test_file_parsing_with_predefined() {
         let mut symbol_table = symbol_table_setup();
-        let mut asm_file = File::open("symbol_test_3.txt").unwrap();
-        let mut intm_file = File::create("intm3.txt").unwrap();
-        symbol_table.parse_file(asm_file, intm_file);
+        resolve_file(&mut symbol_table, "symbol_test_3.txt", &mut Diagnostics::new());
         assert_eq!(*symbol_table.symbol_map.get(&"i".to_string()).unwrap(), 16);
     }
 
+    #[test]
+    fn resolve_indented_source() {
+        // the course's examples indent instructions under their labels, and may use CRLF line endings
+        let source = "// count\r\n   @i\r\n\tM=1\r\n(LOOP)\r\n    @i\r\n    M = M + 1 // next\r\n    @LOOP\r\n    0;JMP\r\n";
+        let mut symbol_table = symbol_table_setup();
+        let mut diagnostics = Diagnostics::new();
+        let instructions = symbol_table.resolve(source, "test.asm", &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        assert_eq!(*symbol_table.symbol_map.get(&"LOOP".to_string()).unwrap(), 2);
+        assert_eq!(instructions.iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+                   vec!["@16", "M=1", "@16", "M=M+1", "@2", "0;JMP"]);
+    }
+
+    #[test]
+    fn test_file_parsing_collects_errors() {
+        let mut symbol_table = symbol_table_setup();
+        let mut diagnostics = Diagnostics::new();
+        resolve_file(&mut symbol_table, "error_test.txt", &mut diagnostics);
+        let lines: Vec<usize> = diagnostics.errors().iter().map(|e| e.span().line).collect();
+        assert_eq!(lines, vec![2, 5]);
+        // the well-formed label after the errors is still resolved
+        assert_eq!(*symbol_table.symbol_map.get(&"END".to_string()).unwrap(), 4);
+    }
+
+    #[test]
+    fn c_decoder_suggestions() {
+        let decoder = c_decoder_setup();
+        let error = AsmError::UnknownDest { dest: "MM".to_string(), span: Span::file("test.asm") };
+        assert_eq!(decoder.suggest(&error), Some("did you mean 'AM', 'M' or 'MD'?".to_string()));
+        let error = AsmError::UnknownJump { jump: "JMPP".to_string(), span: Span::file("test.asm") };
+        assert_eq!(decoder.suggest(&error), Some("did you mean 'JMP'?".to_string()));
+        let error = AsmError::MalformedLabel {
+            label: "(END".to_string(),
+            reason: "missing ')'".to_string(),
+            span: Span::file("test.asm"),
+        };
+        assert_eq!(decoder.suggest(&error), None);
+    }
+
+    #[test]
+    fn resolve_in_memory() {
+        let mut symbol_table = symbol_table_setup();
+        let mut diagnostics = Diagnostics::new();
+        let instructions = symbol_table.resolve("@sum\n(LOOP)\nM=D // store\n@LOOP\n", "test.asm", &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        assert_eq!(instructions.iter().map(|i| i.to_string()).collect::<Vec<_>>(), vec!["@16", "M=D", "@1"]);
+        assert_eq!(symbol_table.instruction_lines, vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        let errors = assemble("(LOOP)\n@LOOP\n  (LOOP)\n0;JMP\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span().line, 3);
+        assert_eq!(errors[0].span().start, 3);
+        assert_eq!(errors[0].message(), "label 'LOOP' is already defined at <source>:1:2");
+    }
+
+    #[test]
+    fn label_redefining_predefined_symbol() {
+        let mut symbol_table = symbol_table_setup();
+        let mut diagnostics = Diagnostics::new();
+        let instructions = symbol_table.resolve("@0\n(SCREEN)\n@SCREEN\n", "test.asm", &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        assert_eq!(instructions[1].to_string(), "@16384");
+        assert_eq!(diagnostics.warnings().len(), 1);
+        assert_eq!(diagnostics.warnings()[0].message(),
+                   "label 'SCREEN' redefines a predefined symbol, which keeps its address");
+        assert_eq!(assemble("(SCREEN)\n@SCREEN\n0;JMP\n").unwrap(), vec![0x4000, 0xea87]);
+    }
+
+    #[test]
+    fn variable_differing_from_label_in_case() {
+        let mut symbol_table = symbol_table_setup();
+        let mut diagnostics = Diagnostics::new();
+        symbol_table.resolve("(LOOP)\n@loop\n0;JMP\n@i\n", "test.asm", &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        let warnings = diagnostics.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].span().line, warnings[0].span().start), (2, 1));
+        assert_eq!(warnings[0].message(), "variable 'loop' differs only in case from label 'LOOP' on line 1");
+    }
+
+    #[test]
+    fn program_too_large_for_rom() {
+        assert_eq!(assemble(&"D=0\n".repeat(ROM_SIZE)).unwrap().len(), ROM_SIZE);
+        let errors = assemble(&format!("{}(END)\n@END\n0;JMP\n", "D=0\n".repeat(ROM_SIZE - 1))).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "<source>:32770:1: the program has 32769 instructions, but ROM only holds 32768");
+    }
+
+    #[test]
+    fn variables_up_to_screen() {
+        // variables start at 16, so 16368 of them fit below SCREEN
+        let source: String = (0..16368).map(|i| format!("@v{}\n", i)).collect();
+        assert_eq!(*assemble(&source).unwrap().last().unwrap(), SCREEN - 1);
+        let errors = assemble(&format!("{}@R0\n@extra\n", source)).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "<source>:16370:2: variable 'extra' would be at address 16384, \
+                                           but variables must end before SCREEN at 16384");
+    }
+
+    #[test]
+    fn assemble_max() {
+        let source = "// Computes R2 = max(R0, R1)
+@R0
+D=M
+@R1
+D=D-M
+@OUTPUT_FIRST
+D;JGT
+@R1
+D=M
+@OUTPUT_D
+0;JMP
+(OUTPUT_FIRST)
+@R0
+D=M
+(OUTPUT_D)
+@R2
+M=D
+(INFINITE_LOOP)
+@INFINITE_LOOP
+0;JMP
+";
+        assert_eq!(assemble(source).unwrap(), vec![
+            0x0000, 0xfc10, 0x0001, 0xf4d0, 0x000a, 0xe301, 0x0001, 0xfc10,
+            0x000c, 0xea87, 0x0000, 0xfc10, 0x0002, 0xe308, 0x000e, 0xea87,
+        ]);
+    }
+
+    #[test]
+    fn assemble_reports_every_error() {
+        let errors = assemble("@R0\nD=D+X\n(LOOP\n@40000\n0;JMP\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|e| e.span().line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+    }
 }
@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+
+use error::SourceLine;
+use instruction::Instruction;
+use symbol_map::SymbolMap;
+use {parse_line, Assembly};
+
+/// Writes the listing of an assembled program: every source line, next to the
+/// ROM address and machine word of its instruction, followed by the labels and
+/// variables with their addresses.
+///
+/// ```text
+///  addr  hex   binary            line  source
+///                                   1  // Computes R2 = max(R0, R1)
+///     0  0000  0000000000000000     2  @R0
+///     1  fc10  1111110000010000     3  D=M
+///    10                            12  (OUTPUT_FIRST)
+/// ```
+///
+/// Label lines show the address of the instruction after the label.
+///
+/// Arguments:
+///
+/// * writer - where to write the listing
+/// * source - the assembly source text
+/// * file_name - the name of the source
+/// * assembly - the program assembled from the source, which must have no errors
+pub fn write_listing<W: Write>(writer: &mut W, source: &str, file_name: &str,
+                               assembly: &Assembly) -> io::Result<()> {
+    let symbol_table = &assembly.symbol_table;
+    let mut addresses = symbol_table.instruction_lines.iter().enumerate().peekable();
+    writeln!(writer, " addr  hex   binary            line  source")?;
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let columns = match addresses.peek() {
+            Some(&(address, &line)) if line == number => {
+                addresses.next();
+                let word = assembly.words[address];
+                format!("{:>5}  {:04x}  {:016b}", address, word, word)
+            }
+            _ => match parse_line(&SourceLine::new(file_name, number, text)) {
+                // the symbol map would give a predefined symbol's address for a label that shadows it
+                Ok(Some(Instruction::Label(_))) => {
+                    let address = addresses.peek().map_or(symbol_table.instruction_lines.len(), |&(address, _)| address);
+                    format!("{:>5}  {:4}  {:16}", address, "", "")
+                }
+                _ => format!("{:5}  {:4}  {:16}", "", "", ""),
+            },
+        };
+        writeln!(writer, "{}", format!("{}  {:>4}  {}", columns, number, text).trim_end())?;
+    }
+
+    let symbols = SymbolMap::from_symbol_table(symbol_table);
+    let mut entries: Vec<(&str, &str, u16)> = symbols.labels().iter()
+        .map(|(name, address)| (name.as_str(), "label", *address))
+        .chain(symbols.variables().iter().map(|(name, address)| (name.as_str(), "variable", *address)))
+        .collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    entries.sort();
+    let width = entries.iter().map(|&(name, _, _)| name.len()).max().unwrap_or(0).max("symbol".len());
+    writeln!(writer)?;
+    writeln!(writer, "{:<width$}  kind      address", "symbol", width = width)?;
+    for (name, kind, address) in entries {
+        writeln!(writer, "{:<width$}  {:<8}  {:>7}", name, kind, address, width = width)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {assemble_source, CDecoder, Diagnostics};
+
+    #[test]
+    fn write_program_listing() {
+        let source = "// count down\n@10\nD=A\n(LOOP)\n@i\nM=D\n@LOOP\nD=D-1;JGT\n";
+        let mut diagnostics = Diagnostics::new();
+        let assembly = assemble_source(source, "Test.asm", &CDecoder::default(), &mut diagnostics);
+        let mut listing = Vec::new();
+        write_listing(&mut listing, source, "Test.asm", &assembly).unwrap();
+        assert_eq!(String::from_utf8(listing).unwrap(), "\
+\x20addr  hex   binary            line  source
+                                  1  // count down
+    0  000a  0000000000001010     2  @10
+    1  ec10  1110110000010000     3  D=A
+    2                             4  (LOOP)
+    2  0010  0000000000010000     5  @i
+    3  e308  1110001100001000     6  M=D
+    4  0002  0000000000000010     7  @LOOP
+    5  e391  1110001110010001     8  D=D-1;JGT
+
+symbol  kind      address
+LOOP    label           2
+i       variable       16
+");
+    }
+
+    #[test]
+    fn label_shadowing_predefined_symbol() {
+        let source = "@0\n(SCREEN)\n@SCREEN\n(END)\n";
+        let mut diagnostics = Diagnostics::new();
+        let assembly = assemble_source(source, "Test.asm", &CDecoder::default(), &mut diagnostics);
+        let mut listing = Vec::new();
+        write_listing(&mut listing, source, "Test.asm", &assembly).unwrap();
+        assert_eq!(String::from_utf8(listing).unwrap(), "\
+\x20addr  hex   binary            line  source
+    0  0000  0000000000000000     1  @0
+    1                             2  (SCREEN)
+    1  4000  0100000000000000     3  @SCREEN
+    2                             4  (END)
+
+symbol  kind      address
+END     label           2
+");
+    }
+}
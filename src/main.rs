@@ -1,53 +1,775 @@
 extern crate hack_assembler;
 use hack_assembler::*;
-use std::fs::File;
-use std::io::{Seek, SeekFrom, BufRead, BufReader, Write, BufWriter};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
+const USAGE: &str = "usage: hack_assembler [options] <file.asm|file.vm|file.jack|dir>...
+       hack_assembler disassemble [options] <file.hack>...
+       hack_assembler screen [options] <file.asm|file.hack>...
+       hack_assembler test <file.tst>...
+
+Assembles Hack assembly files into .hack machine code. Unless --output is
+given, each .hack file is written next to its .asm input.
+
+VM programs are translated to assembly and assembled in one step. An input
+that is a .vm file, or a directory of them, is written to a .hack file
+named after it, inside the directory for a directory. The bootstrap code
+that calls Sys.init is added when the program defines Sys.init.
+
+Jack programs are compiled to VM code first, so a directory of .jack files
+becomes a .hack file in one step. A directory's .vm files, such as the OS,
+are included unless a .jack file of the same name replaces them.
+
+The disassemble command turns machine code back into assembly, written to
+standard output unless --output is given. Words that aren't instructions
+are listed as data. Given the .sym file written by --emit-symbol-map, it
+also puts back the program's labels and variable names.
+
+The screen command runs each program on a Hack CPU and writes a snapshot of
+the 512x256 screen when it halts, or after --cycles instructions. Programs
+that never halt, such as games, stop after 10000000 instructions. A key
+script, with lines such as '10000 up' or '12000 none', replays key presses
+into the keyboard register on the cycles given.
+
+The test command runs nand2tetris CPU test scripts: it writes each script's
+output-file and compares it against its compare-to file.
+
+options:
+    -o, --output <path>   output file, or output directory when several inputs are given
+    -f, --format <fmt>    machine code format: hack (default), hex, or bin for raw 16-bit words
+    --max-errors <n>      report at most n errors per file
+    --extended-syntax     also accept hex (@0x4000), binary (@0b101) and character (@'A') constants
+    --emit-intermediate   also write the symbol-resolved assembly to a .intm file
+    --emit-symbol-map     also write the addresses of labels and variables to a .sym file
+    --symbols <path>      also export every symbol with its kind and address, sorted by address; with
+                          several inputs, <path> must be a directory, where each export is named
+                          after its input with a .sym, .json or .csv extension
+    --symbols-format <fmt>
+                          symbol export format: text, json or csv (default: from the extension of
+                          the --symbols path, or text)
+    --listing             also write a listing of addresses, machine words and source lines to a .lst file
+    --emit-source-map     also write the source file, line and column of each ROM address to a .map file
+    --source-map-format <fmt>
+                          source map format: text (default) or json
+    --emit-asm            also write the assembly translated from VM code to a .asm file
+    --emit-vm             also write the VM code compiled from each .jack file to a .vm file
+    --emit-xml            also write the tokens and parse tree of each .jack file to XxxT.xml and Xxx.xml
+    -s, --symbol-map <path>
+                          symbol map to use when disassembling
+    --cycles <n>          take the screen snapshot after at most n instructions
+    --image-format <fmt>  screen snapshot format: png (default, unless --output ends in .pbm) or pbm
+    --keys <path>         key script to replay while running a program
+    -h, --help            print this message";
+
+/// The default limit on the instructions run before a screen snapshot
+const DEFAULT_CYCLES: u64 = 10_000_000;
+
+/// What the program has been asked to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Assemble,
+    Disassemble,
+    Screen,
+    Test,
+}
+
+/// Command line options for an assembler run
+struct Options {
+    command: Command,
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    max_errors: Option<usize>,
+    syntax: Syntax,
+    format: OutputFormat,
+    emit_intermediate: bool,
+    emit_symbol_map: bool,
+    symbols: Option<PathBuf>,
+    symbols_format: Option<SymbolFormat>,
+    listing: bool,
+    emit_source_map: bool,
+    source_map_format: SourceMapFormat,
+    emit_asm: bool,
+    emit_vm: bool,
+    emit_xml: bool,
+    symbol_map: Option<PathBuf>,
+    cycles: u64,
+    image_format: Option<ImageFormat>,
+    keys: Option<PathBuf>,
+}
+
+/// Parses the command line arguments (without the program name)
+///
+/// Returns: the parsed options, or None if help was requested
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Option<Options>, String> {
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut max_errors = None;
+    let mut syntax = Syntax::Standard;
+    let mut format = OutputFormat::Hack;
+    let mut emit_intermediate = false;
+    let mut emit_symbol_map = false;
+    let mut symbols = None;
+    let mut symbols_format = None;
+    let mut listing = false;
+    let mut emit_source_map = false;
+    let mut source_map_format = SourceMapFormat::Text;
+    let mut emit_asm = false;
+    let mut emit_vm = false;
+    let mut emit_xml = false;
+    let mut symbol_map = None;
+    let mut cycles = DEFAULT_CYCLES;
+    let mut image_format = None;
+    let mut keys = None;
+    let mut args = args.peekable();
+    let command = match args.peek().map(|arg| arg.as_str()) {
+        Some("disassemble") => Command::Disassemble,
+        Some("screen") => Command::Screen,
+        Some("test") => Command::Test,
+        _ => Command::Assemble,
+    };
+    if command != Command::Assemble {
+        args.next();
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => {
+                let path = args.next().ok_or(format!("{} requires a path", arg))?;
+                output = Some(PathBuf::from(path));
+            }
+            _ if arg.starts_with("--output=") => {
+                output = Some(PathBuf::from(&arg["--output=".len()..]));
+            }
+            "-f" | "--format" => {
+                let name = args.next().ok_or(format!("{} requires a format", arg))?;
+                format = OutputFormat::from_name(&name).ok_or(format!("unknown output format '{}'", name))?;
+            }
+            "--extended-syntax" => syntax = Syntax::Extended,
+            "--emit-intermediate" => emit_intermediate = true,
+            "--emit-symbol-map" => emit_symbol_map = true,
+            "--symbols" => {
+                let path = args.next().ok_or(format!("{} requires a path", arg))?;
+                symbols = Some(PathBuf::from(path));
+            }
+            "--symbols-format" => {
+                let name = args.next().ok_or(format!("{} requires a format", arg))?;
+                symbols_format = Some(SymbolFormat::from_name(&name).ok_or(format!("unknown symbol format '{}'", name))?);
+            }
+            "--listing" => listing = true,
+            "--emit-source-map" => emit_source_map = true,
+            "--source-map-format" => {
+                let name = args.next().ok_or(format!("{} requires a format", arg))?;
+                source_map_format = SourceMapFormat::from_name(&name)
+                    .ok_or(format!("unknown source map format '{}'", name))?;
+            }
+            "--emit-asm" => emit_asm = true,
+            "--emit-vm" => emit_vm = true,
+            "--emit-xml" => emit_xml = true,
+            "-s" | "--symbol-map" => {
+                let path = args.next().ok_or(format!("{} requires a path", arg))?;
+                symbol_map = Some(PathBuf::from(path));
+            }
+            "--cycles" => {
+                let max = args.next().ok_or(format!("{} requires a number", arg))?;
+                cycles = max.parse().map_err(|_| format!("invalid cycle count '{}'", max))?;
+            }
+            "--image-format" => {
+                let name = args.next().ok_or(format!("{} requires a format", arg))?;
+                image_format = Some(ImageFormat::from_name(&name).ok_or(format!("unknown image format '{}'", name))?);
+            }
+            "--keys" => {
+                let path = args.next().ok_or(format!("{} requires a path", arg))?;
+                keys = Some(PathBuf::from(path));
+            }
+            "--max-errors" => {
+                let max = args.next().ok_or(format!("{} requires a number", arg))?;
+                max_errors = Some(max.parse().map_err(|_| format!("invalid error limit '{}'", max))?);
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option '{}'", arg));
+            }
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if inputs.is_empty() {
+        return Err("no input files".to_string());
+    }
+    Ok(Some(Options {
+        command,
+        inputs,
+        output,
+        max_errors,
+        syntax,
+        format,
+        emit_intermediate,
+        emit_symbol_map,
+        symbols,
+        symbols_format,
+        listing,
+        emit_source_map,
+        source_map_format,
+        emit_asm,
+        emit_vm,
+        emit_xml,
+        symbol_map,
+        cycles,
+        image_format,
+        keys,
+    }))
+}
+
+/// Works out where the output file for an input should be written
+///
+/// Arguments:
+///
+/// * input - path of the .asm file
+/// * output - the --output path, if one was given
+/// * multiple - whether more than one input is being assembled
+/// * extension - the extension of the output format
+fn output_path(input: &Path, output: Option<&Path>, multiple: bool, extension: &str) -> Result<PathBuf, String> {
+    match output {
+        // a directory of .vm files is written inside it, as nand2tetris does
+        None if input.is_dir() => {
+            // "." and ".." only have a name once resolved
+            let absolute = input.canonicalize().map_err(|e| format!("{}: {}", input.display(), e))?;
+            let name = absolute.file_name().ok_or(format!("{}: not a directory", input.display()))?;
+            Ok(input.join(name).with_extension(extension))
+        }
+        None => Ok(input.with_extension(extension)),
+        Some(out) if out.is_dir() => {
+            let stem = input.file_stem().ok_or(format!("{}: not a file", input.display()))?;
+            Ok(out.join(stem).with_extension(extension))
+        }
+        Some(out) if multiple => Err(format!(
+            "{}: --output must be an existing directory when assembling several files",
+            out.display()
+        )),
+        Some(out) => Ok(out.to_path_buf()),
+    }
+}
+
+/// Works out where --symbols exports the symbols of an input, and in what format
+///
+/// Arguments:
+///
+/// * input - path of the input
+/// * output - path of the input's machine code, next to which --emit-symbol-map writes
+/// * path - the --symbols path
+/// * options - the command line options
+fn symbols_path(input: &Path, output: &Path, path: &Path, options: &Options) -> Result<(PathBuf, SymbolFormat), String> {
+    let format = options.symbols_format
+        .or_else(|| SymbolFormat::from_name(path.extension()?.to_str()?))
+        .unwrap_or(SymbolFormat::Text);
+    let sym_path = if path.is_dir() {
+        let stem = input.file_stem().ok_or(format!("{}: not a file", input.display()))?;
+        path.join(stem).with_extension(format.extension())
+    } else if options.inputs.len() > 1 {
+        return Err(format!("{}: --symbols must be an existing directory when assembling several files",
+                           path.display()));
+    } else {
+        path.to_path_buf()
+    };
+    if options.emit_symbol_map && same_file(&sym_path, &output.with_extension("sym")) {
+        return Err(format!("{}: --symbols would overwrite the symbol map written by --emit-symbol-map",
+                           sym_path.display()));
+    }
+    Ok((sym_path, format))
+}
+
+/// Whether two paths name the same file, such as "Max.sym" and "./Max.sym",
+/// whether or not it exists yet
+fn same_file(a: &Path, b: &Path) -> bool {
+    let directory = |path: &Path| -> Option<PathBuf> {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        parent.canonicalize().ok()
+    };
+    a.file_name() == b.file_name() && (a == b || directory(a).is_some_and(|dir| Some(dir) == directory(b)))
+}
+
+fn create(path: &Path) -> Result<File, String> {
+    File::create(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn has_extension(path: &Path, name: &str) -> bool {
+    path.extension().is_some_and(|extension| extension == name)
+}
+
+/// Whether an input is translated to assembly: a .vm or .jack file, or a directory of them
+fn is_vm_input(input: &Path) -> bool {
+    input.is_dir() || has_extension(input, "vm") || has_extension(input, "jack")
+}
+
+/// Compiles a .jack file to VM code, writing the files asked for by --emit-vm and --emit-xml
+fn compile_jack_file(path: &Path, source: &str, options: &Options,
+                     diagnostics: &mut Diagnostics) -> Result<String, String> {
+    let file_name = path.display().to_string();
+    let write = |path: &Path, text: &str| fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e));
+    let vm = compile_jack(source, &file_name, diagnostics);
+    if diagnostics.has_errors() {
+        return Ok(vm);
+    }
+    if options.emit_vm {
+        write(&path.with_extension("vm"), &vm)?;
+    }
+    if options.emit_xml {
+        // the source compiled, so it also tokenizes and parses
+        let stem = path.file_stem().ok_or(format!("{}: not a file", file_name))?.to_string_lossy();
+        let tokens = tokens_xml(source, &file_name).map_err(|e| e.to_string())?;
+        write(&path.with_file_name(format!("{}T.xml", stem)), &tokens)?;
+        let tree = parse_tree_xml(source, &file_name).map_err(|e| e.to_string())?;
+        write(&path.with_extension("xml"), &tree)?;
+    }
+    Ok(vm)
+}
+
+/// Reads the assembly source of an input, translating it first if it's VM or
+/// Jack code. Errors in the code are collected in `diagnostics`, and the text
+/// of every file read is kept in `sources` to report them with.
+///
+/// Returns: the assembly source, the file name to report its errors under, and
+/// for VM and Jack code the VM command each line of assembly was generated for
+fn read_source(input: &Path, options: &Options, sources: &mut Sources,
+               diagnostics: &mut Diagnostics) -> Result<(String, String, Option<Translation>), String> {
+    let mut read = |path: &Path| -> Result<String, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        sources.insert(&path.display().to_string(), text.clone());
+        Ok(text)
+    };
+    if !is_vm_input(input) {
+        return Ok((read(input)?, input.display().to_string(), None));
+    }
+    let paths = if input.is_dir() {
+        let entries = fs::read_dir(input).map_err(|e| format!("{}: {}", input.display(), e))?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| has_extension(path, "jack") || has_extension(path, "vm"))
+            .collect();
+        // a .vm file is left over from compiling the .jack file of the same name
+        let compiled: Vec<PathBuf> = paths.iter()
+            .filter(|path| has_extension(path, "jack"))
+            .map(|path| path.with_extension("vm"))
+            .collect();
+        paths.retain(|path| !compiled.contains(path));
+        if paths.is_empty() {
+            return Err(format!("{}: no .vm or .jack files in directory", input.display()));
+        }
+        paths.sort();
+        paths
+    } else {
+        vec![input.to_path_buf()]
+    };
+    let mut files = Vec::new();
+    for path in paths.iter() {
+        let source = read(path)?;
+        if has_extension(path, "jack") {
+            let vm = compile_jack_file(path, &source, options, diagnostics)?;
+            files.push((path.with_extension("vm").display().to_string(), vm));
+        } else {
+            files.push((path.display().to_string(), source));
+        }
+    }
+    // errors in generated code point at files that may not exist, or are
+    // stale, so they are reported from the generated text
+    for (name, vm) in files.iter() {
+        sources.insert(name, vm.clone());
+    }
+    if diagnostics.has_errors() {
+        return Ok((String::new(), input.display().to_string(), None));
+    }
+    let files: Vec<(&str, &str)> = files.iter().map(|(name, source)| (name.as_str(), source.as_str())).collect();
+    let translation = translate_vm_program(&files, diagnostics);
+    let file_name = output_path(input, None, false, "asm")?.display().to_string();
+    sources.insert(&file_name, translation.asm.clone());
+    Ok((translation.asm.clone(), file_name, Some(translation)))
+}
+
+/// Assembles a single .asm file, or VM program, into a .hack file. Errors in
+/// the source are collected in `diagnostics`, and the output is only written
+/// if there are none.
+///
+/// Returns: an error if a file couldn't be read or written
+fn assemble_file(input: &Path, output: &Path, options: &Options, c_decoder: &CDecoder,
+                 sources: &mut Sources, diagnostics: &mut Diagnostics) -> Result<(), String> {
+    let (source, file_name, translation) = read_source(input, options, sources, diagnostics)?;
+    if diagnostics.has_errors() {
+        return Ok(());
+    }
+    if options.emit_asm && is_vm_input(input) {
+        let asm_path = output.with_extension("asm");
+        fs::write(&asm_path, &source).map_err(|e| format!("{}: {}", asm_path.display(), e))?;
+    }
+    let assembly = assemble_source_with_syntax(&source, &file_name, c_decoder, options.syntax, diagnostics);
+    if diagnostics.has_errors() {
+        return Ok(());
+    }
+    // worked out before anything is written, so that a clash with another output is caught first
+    let symbols_target = match options.symbols {
+        Some(ref path) => Some(symbols_path(input, output, path, options)?),
+        None => None,
+    };
+
+    let mut writer = BufWriter::new(create(output)?);
+    write_words(&mut writer, &assembly.words, options.format)
+        .and_then(|_| writer.flush())
+        .map_err(|e| format!("{}: {}", output.display(), e))?;
+
+    if options.emit_intermediate {
+        // the symbol-resolved listing, one instruction per line
+        let intm_path = output.with_extension("intm");
+        let mut writer = BufWriter::new(create(&intm_path)?);
+        assembly.instructions.iter()
+            .try_for_each(|instruction| writeln!(writer, "{}", instruction))
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("{}: {}", intm_path.display(), e))?;
+    }
+
+    if options.emit_symbol_map {
+        let sym_path = output.with_extension("sym");
+        let mut writer = BufWriter::new(create(&sym_path)?);
+        SymbolMap::from_symbol_table(&assembly.symbol_table).write(&mut writer)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("{}: {}", sym_path.display(), e))?;
+    }
+
+    if let Some((sym_path, format)) = symbols_target {
+        let mut writer = BufWriter::new(create(&sym_path)?);
+        write_symbols(&mut writer, &symbols(&assembly.symbol_table), format)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("{}: {}", sym_path.display(), e))?;
+    }
+
+    if options.listing {
+        let lst_path = output.with_extension("lst");
+        let mut writer = BufWriter::new(create(&lst_path)?);
+        write_listing(&mut writer, &source, &file_name, &assembly)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("{}: {}", lst_path.display(), e))?;
+    }
+
+    if options.emit_source_map {
+        let map_path = output.with_extension("map");
+        let mut writer = BufWriter::new(create(&map_path)?);
+        // a translated program is mapped back to its VM code, as its assembly may not be written
+        let source_map = match translation {
+            Some(ref translation) => SourceMap::translated(translation, &assembly),
+            None => SourceMap::new(&source, &file_name, &assembly),
+        };
+        source_map.write(&mut writer, options.source_map_format)
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("{}: {}", map_path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Disassembles each input file, writing the listings to `output` or to stdout
+fn disassemble_files(options: &Options) -> Result<(), String> {
+    let mut writer: Box<dyn Write> = match options.output {
+        Some(ref path) => Box::new(BufWriter::new(create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    let symbols = match options.symbol_map {
+        Some(ref path) => {
+            let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let symbols = SymbolMap::read(BufReader::new(file), &path.display().to_string())
+                .map_err(|e| e.to_string())?;
+            Some(symbols)
+        }
+        None => None,
+    };
+    for input in options.inputs.iter() {
+        let file = File::open(input).map_err(|e| format!("{}: {}", input.display(), e))?;
+        let words = read_words(BufReader::new(file), options.format, &input.display().to_string())
+            .map_err(|e| e.to_string())?;
+        let listing = match symbols {
+            Some(ref symbols) => disassemble_with_symbols(&words, symbols),
+            None => disassemble(&words),
+        };
+        writer.write_all(listing.as_bytes())
+            .map_err(|e| format!("{}: {}", input.display(), e))?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Loads the program in an input file, assembling it if it's a .asm file or VM
+/// code and otherwise reading it as machine code in the --format format
+///
+/// Returns: the machine words, or None if the source had errors, which are reported
+fn load_program(input: &Path, options: &Options, c_decoder: &CDecoder) -> Result<Option<Vec<u16>>, String> {
+    let file_name = input.display().to_string();
+    if is_vm_input(input) || has_extension(input, "asm") {
+        let mut diagnostics = match options.max_errors {
+            Some(max) => Diagnostics::with_limit(max),
+            None => Diagnostics::new(),
+        };
+        let mut sources = Sources::new();
+        let (source, file_name, _) = read_source(input, options, &mut sources, &mut diagnostics)?;
+        let assembly = assemble_source_with_syntax(&source, &file_name, c_decoder, options.syntax, &mut diagnostics);
+        report(input, &diagnostics, &sources, c_decoder);
+        if diagnostics.has_errors() {
+            return Ok(None);
+        }
+        return Ok(Some(assembly.words));
+    }
+    let file = File::open(input).map_err(|e| format!("{}: {}", file_name, e))?;
+    read_words(BufReader::new(file), options.format, &file_name)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Runs each input program and writes a snapshot of its screen
+///
+/// Returns: whether every program could be run
+fn screen_files(options: &Options) -> Result<bool, String> {
+    let c_decoder = CDecoder::default();
+    let multiple = options.inputs.len() > 1;
+    let format = options.image_format
+        .or_else(|| {
+            let extension = options.output.as_ref()?.extension()?;
+            ImageFormat::from_name(extension.to_str()?)
+        })
+        .unwrap_or(ImageFormat::Png);
+    let keys = match options.keys {
+        Some(ref path) => {
+            let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            KeyScript::read(BufReader::new(file), &path.display().to_string()).map_err(|e| e.to_string())?
+        }
+        None => KeyScript::default(),
+    };
+    let mut success = true;
+    for input in options.inputs.iter() {
+        match screen_file(input, options, &c_decoder, &keys, format, multiple) {
+            Ok(ran) => success &= ran,
+            Err(e) => {
+                eprintln!("{}", Renderer::auto().headline(&e));
+                success = false;
+            }
+        }
+    }
+    Ok(success)
+}
+
+/// Runs a single input program and writes a snapshot of its screen
+///
+/// Returns: whether the program could be run, or an error if a file couldn't
+/// be read or written
+fn screen_file(input: &Path, options: &Options, c_decoder: &CDecoder, keys: &KeyScript,
+               format: ImageFormat, multiple: bool) -> Result<bool, String> {
+    let words = match load_program(input, options, c_decoder)? {
+        Some(words) => words,
+        None => return Ok(false),
+    };
+    let mut emulator = Emulator::new(&words, &input.display().to_string()).map_err(|e| e.to_string())?;
+    emulator.set_key_script(keys.clone());
+    emulator.run(options.cycles);
+
+    let output = output_path(input, options.output.as_deref(), multiple, format.extension())?;
+    let mut writer = BufWriter::new(create(&output)?);
+    write_screen(&mut writer, emulator.screen(), format)
+        .and_then(|_| writer.flush())
+        .map_err(|e| format!("{}: {}", output.display(), e))?;
+    Ok(true)
+}
+
+/// Runs each test script, reporting the result of its comparison
+///
+/// Returns: whether every script ran and matched its compare-to file
+fn test_files(options: &Options) -> bool {
+    let renderer = Renderer::auto();
+    let mut success = true;
+    for input in options.inputs.iter() {
+        match run_test_script(input) {
+            Ok(execution) => {
+                for echo in execution.echoes.iter() {
+                    println!("{}", echo);
+                }
+                let result = if execution.compare_to.is_some() { "comparison ended successfully" } else { "done" };
+                println!("{}: {}", input.display(), result);
+            }
+            Err(e) => {
+                eprintln!("{}", renderer.headline(&e.to_string()));
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+/// Prints the errors and warnings found in an input, in source order, followed
+/// by a summary if there were errors
+///
+/// Arguments:
+///
+/// * input - the input the errors were found in
+/// * diagnostics - the errors and warnings
+/// * sources - the text that was read or generated for the input, which the
+///   errors of a VM program can be spread over
+/// * c_decoder - the tables to suggest mnemonics from
+fn report(input: &Path, diagnostics: &Diagnostics, sources: &Sources, c_decoder: &CDecoder) {
+    let renderer = Renderer::auto();
+    let mut problems: Vec<(&AsmError, bool)> = diagnostics.errors().iter().map(|error| (error, false))
+        .chain(diagnostics.warnings().iter().map(|warning| (warning, true)))
+        .collect();
+    problems.sort_by_key(|&(problem, _)| (&problem.span().file, problem.span().line, problem.span().start));
+    for (problem, is_warning) in problems {
+        if is_warning {
+            eprintln!("{}\n", renderer.render_warning(problem, sources, None));
+            continue;
+        }
+        let help = match *problem {
+            AsmError::ExtendedConstant { .. } => Some("pass --extended-syntax to accept it".to_string()),
+            _ => c_decoder.suggest(problem),
+        };
+        eprintln!("{}\n", renderer.render(problem, sources, help.as_deref()));
+    }
+    if !diagnostics.has_errors() {
+        return;
+    }
+    let message = format!("could not assemble {}: {}", input.display(), diagnostics.summary());
+    eprintln!("{}", renderer.headline(&message));
+}
+
+/// Assembles every input file, reporting the errors found in each. A file that
+/// can't be read or written is reported and the rest are still assembled.
+///
+/// Returns: whether all of the files assembled without errors
+fn run(options: &Options) -> Result<bool, String> {
+    match options.command {
+        Command::Assemble => {}
+        Command::Disassemble => return disassemble_files(options).map(|_| true),
+        Command::Screen => return screen_files(options),
+        Command::Test => return Ok(test_files(options)),
+    }
+    let c_decoder = CDecoder::default();
+    let multiple = options.inputs.len() > 1;
+    let mut success = true;
+    for input in options.inputs.iter() {
+        let output = match output_path(input, options.output.as_deref(), multiple, options.format.extension()) {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("{}", Renderer::auto().headline(&e));
+                success = false;
+                continue;
+            }
+        };
+        let mut diagnostics = match options.max_errors {
+            Some(max) => Diagnostics::with_limit(max),
+            None => Diagnostics::new(),
+        };
+        let mut sources = Sources::new();
+        let result = assemble_file(input, &output, options, &c_decoder, &mut sources, &mut diagnostics);
+        report(input, &diagnostics, &sources, &c_decoder);
+        if let Err(e) = result {
+            eprintln!("{}", Renderer::auto().headline(&e));
+            success = false;
+        } else if diagnostics.has_errors() {
+            success = false;
+        }
+    }
+    Ok(success)
+}
 
 fn main() {
-    // initialize objects
-    let dest_file = File::open("dest_file.txt").unwrap();
-    let comp_file = File::open("comp_file.txt").unwrap();
-    let jump_file = File::open("jump_file.txt").unwrap();
-    let mut predef_file = File::open("predefined_symbols.txt").unwrap();
-
-    let a_decoder = ADecoder::new();
-    let c_decoder = CDecoder::new(dest_file, comp_file, jump_file);
-
-    let asm_file_root = "/home/chris/Dropbox/nand2tetris/nand2tetris/projects/06";
-    let file_paths = vec!["add/Add.asm", "max/Max.asm", "pong/Pong.asm", "rect/Rect.asm"];
-    for file_path in file_paths.iter() {
-        predef_file.seek(SeekFrom::Start(0)); // rewind the file
-        let mut symbol_table = SymbolTable::new(predef_file.try_clone().unwrap());
-        if !symbol_table.symbol_map.contains_key(&"SCREEN".to_string()) {
-            println!("Screen key doesn't exist.");
-        }
-        if !symbol_table.symbol_map.contains_key(&"KBD".to_string()) {
-            println!("keyboard key doesn't exist");
-        }
-        let asm_file = File::open(format!("{}/{}", asm_file_root, file_path)).unwrap();
-        let file_name = file_path.split(|c| c == '/' || c == '.').collect::<Vec<&str>>()[1];
-        {
-            let mut intm_file = File::create(format!("{}/{}.{}", asm_file_root, file_name, "intm")).unwrap();
-            symbol_table.parse_file(asm_file, intm_file.try_clone().unwrap());
-        }
-        let mut intm_file = File::open(format!("{}/{}.intm", asm_file_root, file_name)).unwrap();
-        let bin_file = File::create(format!("{}/{}.hack", asm_file_root, file_name)).unwrap();
-        let reader = BufReader::new(intm_file.try_clone().unwrap());
-        let mut writer = BufWriter::new(bin_file);
-        for line in reader.lines() {
-            let unwrapped_line = line.unwrap();
-            let (parsed_line, info_map) = parse_line(unwrapped_line.as_str());
-            let mut bin_line = String::new(); // the binary translation of the instruction line
-            if *info_map.get("a_instruction").unwrap() {
-                bin_line.push_str(a_decoder.decode(parsed_line, &info_map).as_str());
-            } else {
-                bin_line.push_str(c_decoder.decode(parsed_line, &info_map).as_str());
-            }
-            bin_line.push('\n');
-            writer.write(bin_line.as_bytes());
-        }
-
-    }
-    
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    match run(&options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(msg) => {
+            eprintln!("error: {}", msg);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        parse(args).err().expect("expected an error")
+    }
+
+    /// An empty directory of its own under the system temp directory
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("hack_assembler_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = parse(&["--max-errors", "3", "-f", "hex", "--output=out", "--extended-syntax", "a.asm", "b.asm"])
+            .unwrap().unwrap();
+        assert_eq!(options.command, Command::Assemble);
+        assert_eq!(options.inputs, vec![PathBuf::from("a.asm"), PathBuf::from("b.asm")]);
+        assert_eq!(options.output, Some(PathBuf::from("out")));
+        assert_eq!(options.max_errors, Some(3));
+        assert_eq!(options.format, OutputFormat::Hex);
+        assert_eq!(options.syntax, Syntax::Extended);
+
+        let options = parse(&["disassemble", "-s", "Max.sym", "Max.hack"]).unwrap().unwrap();
+        assert_eq!(options.command, Command::Disassemble);
+        assert_eq!(options.symbol_map, Some(PathBuf::from("Max.sym")));
+        assert!(parse(&["a.asm", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_missing_values() {
+        assert_eq!(parse_error(&["a.asm", "-o"]), "-o requires a path");
+        assert_eq!(parse_error(&["a.asm", "--symbols"]), "--symbols requires a path");
+        assert_eq!(parse_error(&["a.asm", "--format"]), "--format requires a format");
+        assert_eq!(parse_error(&["a.asm", "--max-errors"]), "--max-errors requires a number");
+        assert_eq!(parse_error(&["a.asm", "--max-errors", "many"]), "invalid error limit 'many'");
+        assert_eq!(parse_error(&["a.asm", "-f", "octal"]), "unknown output format 'octal'");
+        assert_eq!(parse_error(&[]), "no input files");
+        assert_eq!(parse_error(&["--listing"]), "no input files");
+    }
+
+    #[test]
+    fn parse_unknown_flags() {
+        assert_eq!(parse_error(&["a.asm", "--frobnicate"]), "unknown option '--frobnicate'");
+        assert_eq!(parse_error(&["-x", "a.asm"]), "unknown option '-x'");
+        // a lone "-" is a file name
+        assert_eq!(parse(&["-"]).unwrap().unwrap().inputs, vec![PathBuf::from("-")]);
+    }
+
+    #[test]
+    fn output_next_to_input() {
+        assert_eq!(output_path(Path::new("prog/Max.asm"), None, false, "hack").unwrap(),
+                   PathBuf::from("prog/Max.hack"));
+        assert_eq!(output_path(Path::new("Max.asm"), Some(Path::new("out.bin")), false, "bin").unwrap(),
+                   PathBuf::from("out.bin"));
+    }
+
+    #[test]
+    fn output_to_directory() {
+        let dir = temp_dir("output");
+        assert_eq!(output_path(Path::new("prog/Max.asm"), Some(&dir), true, "hack").unwrap(), dir.join("Max.hack"));
+        // a directory of .vm files is written inside it, named after it
+        let program = dir.join("Pong");
+        fs::create_dir(&program).unwrap();
+        assert_eq!(output_path(&program, None, false, "hack").unwrap(), program.join("Pong.hack"));
+
+        let file = dir.join("out.hack");
+        let error = output_path(Path::new("Max.asm"), Some(&file), true, "hack").unwrap_err();
+        assert!(error.ends_with("--output must be an existing directory when assembling several files"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
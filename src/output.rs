@@ -0,0 +1,160 @@
+use std::io::{self, BufRead, Write};
+
+use error::{AsmError, Span};
+
+/// The formats that machine words can be written out in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One 16-digit binary string per line, as read by the nand2tetris tools
+    Hack,
+    /// One 4-digit hexadecimal word per line
+    Hex,
+    /// Raw big-endian 16-bit words
+    Binary,
+}
+
+impl OutputFormat {
+    /// Looks up a format by its command line name: hack, hex or bin
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "hack" => Some(OutputFormat::Hack),
+            "hex" => Some(OutputFormat::Hex),
+            "bin" => Some(OutputFormat::Binary),
+            _ => None,
+        }
+    }
+
+    /// The file extension for the format
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            OutputFormat::Hack => "hack",
+            OutputFormat::Hex => "hex",
+            OutputFormat::Binary => "bin",
+        }
+    }
+}
+
+/// Writes machine words in the given format
+pub fn write_words<W: Write>(writer: &mut W, words: &[u16], format: OutputFormat) -> io::Result<()> {
+    for word in words.iter() {
+        match format {
+            OutputFormat::Hack => writeln!(writer, "{:016b}", word)?,
+            OutputFormat::Hex => writeln!(writer, "{:04x}", word)?,
+            OutputFormat::Binary => writer.write_all(&word.to_be_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+/// Reads machine words in the given format, as written by write_words
+///
+/// Arguments:
+///
+/// * reader - the machine code
+/// * format - the format of the machine code
+/// * file_name - the name of the file, used in errors
+pub fn read_words<R: BufRead>(mut reader: R, format: OutputFormat, file_name: &str) -> Result<Vec<u16>, AsmError> {
+    let mut words = Vec::new();
+    let (radix, digits) = match format {
+        OutputFormat::Hack => (2, 16),
+        OutputFormat::Hex => (16, 4),
+        OutputFormat::Binary => {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).map_err(|e| AsmError::io(e, file_name))?;
+            if bytes.len() % 2 != 0 {
+                return Err(AsmError::InvalidWord {
+                    text: format!("{:02x}", bytes[bytes.len() - 1]),
+                    span: Span::file(file_name),
+                });
+            }
+            return Ok(bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect());
+        }
+    };
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| AsmError::io(e, file_name))?;
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        match u16::from_str_radix(text, radix) {
+            Ok(word) if text.len() == digits && text.chars().all(|c| c.is_digit(radix)) => words.push(word),
+            _ => {
+                let start = line.find(text).unwrap_or(0);
+                return Err(AsmError::InvalidWord {
+                    text: text.to_string(),
+                    span: Span::new(file_name, index + 1, start, start + text.len()),
+                });
+            }
+        }
+    }
+    Ok(words)
+}
+
+/// Quotes text as a JSON string
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(format: OutputFormat) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_words(&mut out, &[0x0010, 0xea87], format).unwrap();
+        out
+    }
+
+    #[test]
+    fn write_hack() {
+        assert_eq!(written(OutputFormat::Hack), b"0000000000010000\n1110101010000111\n");
+    }
+
+    #[test]
+    fn write_hex() {
+        assert_eq!(written(OutputFormat::Hex), b"0010\nea87\n");
+    }
+
+    #[test]
+    fn write_binary() {
+        assert_eq!(written(OutputFormat::Binary), vec![0x00, 0x10, 0xea, 0x87]);
+    }
+
+    #[test]
+    fn read_back_written_words() {
+        for format in [OutputFormat::Hack, OutputFormat::Hex, OutputFormat::Binary].iter() {
+            let words = read_words(written(*format).as_slice(), *format, "test.hack").unwrap();
+            assert_eq!(words, vec![0x0010, 0xea87]);
+        }
+    }
+
+    #[test]
+    fn read_invalid_word() {
+        let result = read_words("0000000000010000\n00000002\n".as_bytes(), OutputFormat::Hack, "test.hack");
+        match result {
+            Err(AsmError::InvalidWord { text, span }) => {
+                assert_eq!(text, "00000002");
+                assert_eq!(span.line, 2);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn quote_json_strings() {
+        assert_eq!(json_string("C:\\asm\\\"Max\".asm\n"), "\"C:\\\\asm\\\\\\\"Max\\\".asm\\n\"");
+    }
+}
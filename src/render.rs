@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+
+use error::{AsmError, Span};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The text of the sources that errors point into, by file name
+#[derive(Debug, Default)]
+pub struct Sources {
+    files: HashMap<String, String>,
+}
+
+impl Sources {
+    pub fn new() -> Sources {
+        Sources::default()
+    }
+
+    /// Records the text of a file, replacing any text recorded for it before
+    pub fn insert(&mut self, file: &str, text: String) {
+        self.files.insert(file.to_string(), text);
+    }
+
+    /// The text of the line a span points into, if its file was recorded
+    pub fn line(&self, span: &Span) -> Option<&str> {
+        if span.line == 0 {
+            return None;
+        }
+        self.files.get(&span.file)?.lines().nth(span.line - 1)
+    }
+}
+
+/// Renders errors the way rustc does: the message, its location, the
+/// offending source line with carets under the bad token, and an optional hint.
+///
+/// ```text
+/// error: unknown comp mnemonic 'D+X'
+///  --> Max.asm:2:3
+///   |
+/// 2 | D=D+X
+///   |   ^^^
+///   |
+///   = help: did you mean 'D+1', 'D+A' or 'D+M'?
+/// ```
+///
+/// An error that refers to a second location, such as a duplicate label,
+/// shows that location's line as well, marked with dashes.
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Renderer {
+        Renderer { color }
+    }
+
+    /// Initializes a Renderer that uses colour only when stderr, where
+    /// diagnostics are printed, is a terminal. Checking stdout instead would
+    /// write escape codes into `2> errors.log`, and drop colour from errors
+    /// that still reach the terminal when the output is piped, as with
+    /// `disassemble Prog.hack | less`.
+    pub fn auto() -> Renderer {
+        Renderer::new(io::stderr().is_terminal())
+    }
+
+    /// Wraps `text` in an ANSI style when colour is enabled
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Returns an "error: <message>" headline
+    pub fn headline(&self, message: &str) -> String {
+        format!("{}{}", self.paint(RED, "error"), self.paint(BOLD, &format!(": {}", message)))
+    }
+
+    /// Returns a "warning: <message>" headline
+    pub fn warning_headline(&self, message: &str) -> String {
+        format!("{}{}", self.paint(YELLOW, "warning"), self.paint(BOLD, &format!(": {}", message)))
+    }
+
+    /// Renders an error
+    ///
+    /// Arguments:
+    ///
+    /// * error - the error to render
+    /// * sources - the text of the files the error points into
+    /// * help - a hint to show beneath the snippet, such as a suggested mnemonic
+    pub fn render(&self, error: &AsmError, sources: &Sources, help: Option<&str>) -> String {
+        self.render_as(self.headline(&error.message()), RED, error, sources, help)
+    }
+
+    /// Renders a warning, the same way as an error but with a "warning" headline
+    /// and yellow carets
+    pub fn render_warning(&self, warning: &AsmError, sources: &Sources, help: Option<&str>) -> String {
+        self.render_as(self.warning_headline(&warning.message()), YELLOW, warning, sources, help)
+    }
+
+    fn render_as(&self, headline: String, style: &str, error: &AsmError, sources: &Sources,
+                 help: Option<&str>) -> String {
+        let span = error.span();
+        let related = error.related().and_then(|(related, label)| {
+            sources.line(related).map(|text| (related, text, label))
+        });
+        let mut out = headline;
+        out.push('\n');
+        // the gutter is wide enough for the largest line number shown
+        let width = related.map_or(0, |(related, _, _)| related.line.to_string().len())
+            .max(span.line.to_string().len());
+        let gutter = " ".repeat(width);
+        out.push_str(&format!("{}{} {}", gutter, self.paint(BLUE, "-->"), span));
+
+        let bar = self.paint(BLUE, "|");
+        if let Some(source_line) = sources.line(span) {
+            out.push_str(&format!("\n{} {}", gutter, bar));
+            out.push_str(&self.snippet(span, source_line, width, &self.paint(style, &marks('^', span, source_line))));
+        }
+        if let Some((related, source_line, label)) = related {
+            out.push_str(&format!("\n{} {}", gutter, bar));
+            if related.file != span.file {
+                out.push_str(&format!("\n{}{} {}", gutter, self.paint(BLUE, ":::"), related));
+                out.push_str(&format!("\n{} {}", gutter, bar));
+            }
+            let dashes = format!("{} {}", marks('-', related, source_line), label);
+            out.push_str(&self.snippet(related, source_line, width, &self.paint(BLUE, &dashes)));
+        }
+        if let Some(help) = help {
+            if sources.line(span).is_some() {
+                out.push_str(&format!("\n{} {}", gutter, bar));
+            }
+            out.push_str(&format!("\n{} {} {}: {}", gutter, self.paint(BLUE, "="), self.paint(BOLD, "help"), help));
+        }
+        out
+    }
+
+    /// Renders a source line and the marks under the text a span covers
+    fn snippet(&self, span: &Span, source_line: &str, width: usize, marks: &str) -> String {
+        let bar = self.paint(BLUE, "|");
+        let start = span.start.min(source_line.len());
+        // keep tabs in the padding so the marks line up with the token
+        let padding: String = source_line[..start].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let line_label = format!("{:>width$}", span.line, width = width);
+        format!("\n{} {} {}\n{} {} {}{}", self.paint(BLUE, &line_label), bar, source_line,
+                " ".repeat(width), bar, padding, marks)
+    }
+}
+
+/// A run of `mark` as wide as the text a span covers on its line, at least one long
+fn marks(mark: char, span: &Span, source_line: &str) -> String {
+    let start = span.start.min(source_line.len());
+    let end = span.end.clamp(start, source_line.len());
+    mark.to_string().repeat(source_line[start..end].chars().count().max(1))
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Finds the candidates closest to `word`, for "did you mean" hints
+///
+/// Returns: up to three candidates, in sorted order, that share the smallest
+/// edit distance from `word`. Nothing is returned if even the closest
+/// candidates differ in more than half of the word.
+pub fn closest_matches<'a, I: IntoIterator<Item = &'a str>>(word: &str, candidates: I) -> Vec<&'a str> {
+    let max_distance = (word.chars().count() / 2).max(1);
+    let mut best = max_distance;
+    let mut matches = Vec::new();
+    for candidate in candidates {
+        let distance = edit_distance(word, candidate);
+        if distance > max_distance {
+            continue;
+        }
+        if distance < best {
+            best = distance;
+            matches.clear();
+        }
+        if distance == best {
+            matches.push(candidate);
+        }
+    }
+    matches.sort();
+    matches.truncate(3);
+    matches
+}
+
+/// Formats a "did you mean" hint for a list of suggestions
+pub fn did_you_mean(suggestions: &[&str]) -> Option<String> {
+    let quoted: Vec<String> = suggestions.iter().map(|s| format!("'{}'", s)).collect();
+    match quoted.split_last() {
+        None => None,
+        Some((last, [])) => Some(format!("did you mean {}?", last)),
+        Some((last, rest)) => Some(format!("did you mean {} or {}?", rest.join(", "), last)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Span;
+
+    /// sources holding one file, Max.asm
+    fn max_sources(text: &str) -> Sources {
+        let mut sources = Sources::new();
+        sources.insert("Max.asm", text.to_string());
+        sources
+    }
+
+    fn unknown_comp() -> AsmError {
+        AsmError::UnknownComp {
+            comp: "D+X".to_string(),
+            span: Span::new("Max.asm", 2, 2, 5),
+        }
+    }
+
+    #[test]
+    fn render_plain() {
+        let rendered = Renderer::new(false).render(&unknown_comp(), &max_sources("@R0\nD=D+X\n"),
+                                                       Some("did you mean 'D+A'?"));
+        assert_eq!(rendered, "error: unknown comp mnemonic 'D+X'
+ --> Max.asm:2:3
+  |
+2 | D=D+X
+  |   ^^^
+  |
+  = help: did you mean 'D+A'?");
+    }
+
+    #[test]
+    fn render_without_source() {
+        let rendered = Renderer::new(false).render(&unknown_comp(), &Sources::new(), None);
+        assert_eq!(rendered, "error: unknown comp mnemonic 'D+X'\n --> Max.asm:2:3");
+    }
+
+    #[test]
+    fn render_color() {
+        let rendered = Renderer::new(true).render(&unknown_comp(), &max_sources("@R0\nD=D+X\n"), None);
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m"));
+        assert!(rendered.contains("\x1b[1;31m^^^\x1b[0m"));
+    }
+
+    #[test]
+    fn render_warning() {
+        let warning = AsmError::LabelRedefinesPredefined {
+            label: "R5".to_string(),
+            span: Span::new("Max.asm", 3, 1, 3),
+        };
+        let rendered = Renderer::new(false).render_warning(&warning, &max_sources("@0\n\n(R5)\n"), None);
+        assert_eq!(rendered, "warning: label 'R5' redefines a predefined symbol, which keeps its address
+ --> Max.asm:3:2
+  |
+3 | (R5)
+  |  ^^");
+    }
+
+    #[test]
+    fn render_duplicate_label() {
+        let error = AsmError::DuplicateLabel {
+            label: "LOOP".to_string(),
+            span: Span::new("Max.asm", 10, 3, 7),
+            first: Span::new("Max.asm", 2, 1, 5),
+        };
+        let sources = max_sources("@0\n(LOOP)\n\n\n\n\n\n\n\n  (LOOP)\n");
+        assert_eq!(Renderer::new(false).render(&error, &sources, None), "\
+error: label 'LOOP' is already defined at Max.asm:2:2
+  --> Max.asm:10:4
+   |
+10 |   (LOOP)
+   |    ^^^^
+   |
+ 2 | (LOOP)
+   |  ---- first defined here");
+
+        let error = AsmError::DuplicateLabel {
+            label: "LOOP".to_string(),
+            span: Span::new("Other.asm", 1, 1, 5),
+            first: Span::new("Max.asm", 2, 1, 5),
+        };
+        let mut sources = max_sources("@0\n(LOOP)\n");
+        sources.insert("Other.asm", "(LOOP)\n".to_string());
+        assert_eq!(Renderer::new(false).render(&error, &sources, None), "\
+error: label 'LOOP' is already defined at Max.asm:2:2
+ --> Other.asm:1:2
+  |
+1 | (LOOP)
+  |  ^^^^
+  |
+ ::: Max.asm:2:2
+  |
+2 | (LOOP)
+  |  ---- first defined here");
+    }
+
+    #[test]
+    fn carets_follow_tabs() {
+        let error = AsmError::UnknownComp {
+            comp: "D+X".to_string(),
+            span: Span::new("Max.asm", 2, 3, 6),
+        };
+        let rendered = Renderer::new(false).render(&error, &max_sources("@R0\n\tD=D+X\n"), None);
+        assert!(rendered.ends_with("  | \t  ^^^"));
+    }
+
+    #[test]
+    fn closest_mnemonics() {
+        let comps = ["D+1", "D+A", "D+M", "D-A", "0", "!D"];
+        assert_eq!(closest_matches("D+X", comps.iter().cloned()), vec!["D+1", "D+A", "D+M"]);
+        assert_eq!(closest_matches("JMPP", ["JMP", "JEQ"].iter().cloned()), vec!["JMP"]);
+        assert!(closest_matches("QQQQ", ["JMP", "JEQ"].iter().cloned()).is_empty());
+    }
+
+    #[test]
+    fn did_you_mean_hint() {
+        assert_eq!(did_you_mean(&[]), None);
+        assert_eq!(did_you_mean(&["JMP"]), Some("did you mean 'JMP'?".to_string()));
+        assert_eq!(did_you_mean(&["D+1", "D+A", "D+M"]),
+                   Some("did you mean 'D+1', 'D+A' or 'D+M'?".to_string()));
+    }
+}
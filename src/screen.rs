@@ -0,0 +1,181 @@
+use std::io::{self, Write};
+
+/// The width of the Hack screen in pixels
+pub const SCREEN_WIDTH: usize = 512;
+/// The height of the Hack screen in pixels
+pub const SCREEN_HEIGHT: usize = 256;
+/// The number of RAM words mapped to the screen
+pub const SCREEN_WORDS: usize = SCREEN_WIDTH * SCREEN_HEIGHT / 16;
+
+/// The image formats a screen snapshot can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// A binary portable bitmap (P4)
+    Pbm,
+    /// A 1-bit greyscale PNG
+    Png,
+}
+
+impl ImageFormat {
+    /// Looks up a format by its name or file extension: pbm or png
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "pbm" => Some(ImageFormat::Pbm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    /// The file extension for the format
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+/// Packs the screen into rows of bytes with the leftmost pixel in the most
+/// significant bit, where a set bit is a black pixel.
+///
+/// Each screen word holds 16 pixels with the leftmost in its least significant bit.
+fn packed_rows(screen: &[u16; SCREEN_WORDS]) -> Vec<u8> {
+    screen.iter()
+        .flat_map(|word| word.reverse_bits().to_be_bytes())
+        .collect()
+}
+
+/// Writes a snapshot of the screen memory as an image
+///
+/// Arguments:
+///
+/// * writer - where to write the image
+/// * screen - the 8K words of RAM starting at SCREEN
+/// * format - the image format
+pub fn write_screen<W: Write>(writer: &mut W, screen: &[u16; SCREEN_WORDS], format: ImageFormat) -> io::Result<()> {
+    let pixels = packed_rows(screen);
+    match format {
+        ImageFormat::Pbm => {
+            write!(writer, "P4\n{} {}\n", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+            writer.write_all(&pixels)
+        }
+        ImageFormat::Png => write_png(writer, &pixels),
+    }
+}
+
+/// Writes packed 1-bit rows as a greyscale PNG, in which a set bit is white
+fn write_png<W: Write>(writer: &mut W, pixels: &[u8]) -> io::Result<()> {
+    let row_bytes = SCREEN_WIDTH / 8;
+    // every row starts with filter type 0 (none)
+    let mut scanlines = Vec::with_capacity(pixels.len() + SCREEN_HEIGHT);
+    for row in pixels.chunks(row_bytes) {
+        scanlines.push(0);
+        scanlines.extend(row.iter().map(|byte| !byte));
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(SCREEN_WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(SCREEN_HEIGHT as u32).to_be_bytes());
+    // bit depth 1, greyscale, deflate, no filtering method extensions, no interlacing
+    header.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_chunk(writer, b"IHDR", &header)?;
+    write_chunk(writer, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+/// Writes a PNG chunk: its length, type, data and CRC
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()));
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps data in a zlib stream of uncompressed deflate blocks, which
+/// keeps the encoder small; screen snapshots are only 16K
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        stream.push(if last { 1 } else { 0 });
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// The CRC-32 used by PNG chunks
+fn crc32<'a, I: IntoIterator<Item = &'a u8>>(bytes: I) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// The Adler-32 checksum that ends a zlib stream
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen() -> [u16; SCREEN_WORDS] {
+        let mut screen = [0; SCREEN_WORDS];
+        // the top-left pixel, and the last pixel of the second row
+        screen[0] = 0x0001;
+        screen[63] = 0x8000;
+        screen
+    }
+
+    #[test]
+    fn write_pbm() {
+        let mut out = Vec::new();
+        write_screen(&mut out, &screen(), ImageFormat::Pbm).unwrap();
+        let header = b"P4\n512 256\n";
+        assert_eq!(&out[..header.len()], header);
+        let pixels = &out[header.len()..];
+        assert_eq!(pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT / 8);
+        assert_eq!(pixels[0], 0x80);
+        assert_eq!(pixels[127], 0x01);
+        assert_eq!(pixels.iter().filter(|byte| **byte != 0).count(), 2);
+    }
+
+    #[test]
+    fn write_png() {
+        let mut out = Vec::new();
+        write_screen(&mut out, &screen(), ImageFormat::Png).unwrap();
+        assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(&out[out.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        let stream = zlib_stored(b"abc");
+        assert_eq!(stream[..7], [0x78, 0x01, 1, 3, 0, 0xfc, 0xff]);
+        assert_eq!(&stream[7..10], b"abc");
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::{self, Write};
+
+use error::Span;
+use lexer::{self, tokenize_line};
+use output::json_string;
+use vm::Translation;
+use Assembly;
+
+/// The formats that a source map can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceMapFormat {
+    /// One "address file:line:column instruction" line per word
+    Text,
+    /// A JSON array with one object per word
+    Json,
+}
+
+impl SourceMapFormat {
+    /// Looks up a format by its command line name: text or json
+    pub fn from_name(name: &str) -> Option<SourceMapFormat> {
+        match name {
+            "text" => Some(SourceMapFormat::Text),
+            "json" => Some(SourceMapFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Where the instruction at a ROM address came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub address: u16,
+    /// The instruction's text within its source line, without comments
+    pub span: Span,
+    pub text: String,
+}
+
+/// Maps the ROM addresses of an assembled program back to its source, written
+/// next to its machine code for debuggers and emulators to report locations.
+///
+/// The text format has one line per ROM address:
+///
+/// ```text
+/// 0 Max.asm:8:1 @R0
+/// 1 Max.asm:9:1 D=M
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// One location per ROM address, in address order
+    locations: Vec<SourceLocation>,
+}
+
+impl SourceMap {
+    /// Builds the source map of an assembled program
+    ///
+    /// Arguments:
+    ///
+    /// * source - the assembly source text
+    /// * file_name - the name of the source
+    /// * assembly - the program assembled from the source
+    pub fn new(source: &str, file_name: &str, assembly: &Assembly) -> SourceMap {
+        let lines: Vec<&str> = source.lines().collect();
+        let locations = assembly.symbol_table.instruction_lines.iter()
+            .enumerate()
+            .map(|(address, &number)| {
+                let line = lines[number - 1];
+                let tokens = tokenize_line(line);
+                let start = tokens.first().map_or(0, |token| token.start);
+                let text = lexer::code(line, &tokens);
+                SourceLocation {
+                    address: address as u16,
+                    span: Span::new(file_name, number, start, start + text.len()),
+                    text: text.to_string(),
+                }
+            })
+            .collect();
+        SourceMap { locations }
+    }
+
+    /// Builds the source map of a program assembled from translated VM code,
+    /// which maps each ROM address to the VM command it was generated for
+    ///
+    /// Arguments:
+    ///
+    /// * translation - the assembly and the origin of each of its lines
+    /// * assembly - the program assembled from the translation's assembly
+    pub fn translated(translation: &Translation, assembly: &Assembly) -> SourceMap {
+        let locations = assembly.symbol_table.instruction_lines.iter()
+            .enumerate()
+            .map(|(address, &number)| {
+                let (ref span, ref text) = translation.origins[number - 1];
+                SourceLocation { address: address as u16, span: span.clone(), text: text.clone() }
+            })
+            .collect();
+        SourceMap { locations }
+    }
+
+    /// The location of the instruction at a ROM address
+    pub fn location(&self, address: u16) -> Option<&SourceLocation> {
+        self.locations.get(address as usize)
+    }
+
+    /// The locations of every instruction, in address order
+    pub fn locations(&self) -> &[SourceLocation] {
+        &self.locations
+    }
+
+    /// Writes the source map in the given format
+    pub fn write<W: Write>(&self, writer: &mut W, format: SourceMapFormat) -> io::Result<()> {
+        match format {
+            SourceMapFormat::Text => {
+                for location in self.locations.iter() {
+                    writeln!(writer, "{} {} {}", location.address, location.span, location.text)?;
+                }
+            }
+            SourceMapFormat::Json => {
+                writeln!(writer, "[")?;
+                for (index, location) in self.locations.iter().enumerate() {
+                    let separator = if index + 1 < self.locations.len() { "," } else { "" };
+                    writeln!(writer, "  {{\"address\": {}, \"file\": {}, \"line\": {}, \"column\": {}, \"text\": {}}}{}",
+                             location.address, json_string(&location.span.file), location.span.line,
+                             location.span.start + 1, json_string(&location.text), separator)?;
+                }
+                writeln!(writer, "]")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::translate_vm_program;
+    use {assemble_source, CDecoder, Diagnostics};
+
+    fn source_map(source: &str) -> SourceMap {
+        let mut diagnostics = Diagnostics::new();
+        let assembly = assemble_source(source, "Test.asm", &CDecoder::default(), &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        SourceMap::new(source, "Test.asm", &assembly)
+    }
+
+    #[test]
+    fn map_addresses_to_lines() {
+        let map = source_map("// count down\n@10\nD=A // ten\n(LOOP)\n@LOOP\n\tD=D-1;JGT//again\n");
+        let location = map.location(3).unwrap();
+        assert_eq!((location.span.line, location.span.start, location.text.as_str()), (6, 1, "D=D-1;JGT"));
+        assert_eq!(map.location(2).unwrap().span.line, 5);
+        assert_eq!(map.location(4), None);
+    }
+
+    #[test]
+    fn map_addresses_to_vm_commands() {
+        let mut diagnostics = Diagnostics::new();
+        let translation = translate_vm_program(&[("Test.vm", "// seven\n  push constant 7 // push\nneg\n")],
+                                               &mut diagnostics);
+        let assembly = assemble_source(&translation.asm, "Test.asm", &CDecoder::default(), &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        let map = SourceMap::translated(&translation, &assembly);
+        let location = map.location(0).unwrap();
+        assert_eq!((location.span.to_string(), location.text.as_str()), ("Test.vm:2:3".to_string(), "push constant 7"));
+        let last = map.locations().last().unwrap();
+        assert_eq!((last.span.to_string(), last.text.as_str()), ("Test.vm:3:1".to_string(), "neg"));
+        assert!(map.locations().iter().all(|location| location.span.file == "Test.vm"));
+    }
+
+    #[test]
+    fn write_source_map() {
+        let map = source_map("// count down\n@10\nD=A // ten\n(LOOP)\n@LOOP\nD=D-1;JGT\n");
+        let mut text = Vec::new();
+        map.write(&mut text, SourceMapFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(),
+                   "0 Test.asm:2:1 @10\n1 Test.asm:3:1 D=A\n2 Test.asm:5:1 @LOOP\n3 Test.asm:6:1 D=D-1;JGT\n");
+
+        let mut json = Vec::new();
+        SourceMap { locations: map.locations()[..2].to_vec() }.write(&mut json, SourceMapFormat::Json).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), "[\n\
+            \x20 {\"address\": 0, \"file\": \"Test.asm\", \"line\": 2, \"column\": 1, \"text\": \"@10\"},\n\
+            \x20 {\"address\": 1, \"file\": \"Test.asm\", \"line\": 3, \"column\": 1, \"text\": \"D=A\"}\n]\n");
+    }
+}
@@ -0,0 +1,319 @@
+use std::io::{self, BufRead, Write};
+
+use error::{AsmError, Span};
+use output::json_string;
+use SymbolTable;
+
+/// Where a symbol's address came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolKind {
+    /// A symbol every program has, such as SP, R5 or SCREEN
+    Predefined,
+    /// A (LABEL) declaration, which stands for a ROM address
+    Label,
+    /// A variable, allocated a RAM address from 16 up
+    Variable,
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SymbolKind::Predefined => "predefined",
+            SymbolKind::Label => "label",
+            SymbolKind::Variable => "variable",
+        }
+    }
+}
+
+/// A symbol of an assembled program and its address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub address: u16,
+}
+
+/// The formats that the symbol table can be exported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolFormat {
+    /// One "kind name address" line per symbol, which SymbolMap::read understands
+    Text,
+    /// A JSON array with one object per symbol
+    Json,
+    /// A header line, then one "name,kind,address" line per symbol
+    Csv,
+}
+
+impl SymbolFormat {
+    /// Looks up a format by its command line name: text, json or csv
+    pub fn from_name(name: &str) -> Option<SymbolFormat> {
+        match name {
+            "text" => Some(SymbolFormat::Text),
+            "json" => Some(SymbolFormat::Json),
+            "csv" => Some(SymbolFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// The file extension for the format
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            SymbolFormat::Text => "sym",
+            SymbolFormat::Json => "json",
+            SymbolFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Lists every symbol of a resolved SymbolTable, sorted by address. Symbols at
+/// the same address are listed predefined symbols first, then labels, then variables.
+pub fn symbols(symbol_table: &SymbolTable) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = symbol_table.symbol_map.iter()
+        .map(|(name, &address)| {
+            let kind = if symbol_table.labels.contains(name) {
+                SymbolKind::Label
+            } else if symbol_table.variables.contains(name) {
+                SymbolKind::Variable
+            } else {
+                SymbolKind::Predefined
+            };
+            Symbol { name: name.clone(), kind, address: address as u16 }
+        })
+        .collect();
+    symbols.sort_by(|a, b| (a.address, a.kind, &a.name).cmp(&(b.address, b.kind, &b.name)));
+    symbols
+}
+
+/// Writes symbols in the given format
+pub fn write_symbols<W: Write>(writer: &mut W, symbols: &[Symbol], format: SymbolFormat) -> io::Result<()> {
+    match format {
+        SymbolFormat::Text => {
+            for symbol in symbols.iter() {
+                writeln!(writer, "{} {} {}", symbol.kind.name(), symbol.name, symbol.address)?;
+            }
+        }
+        SymbolFormat::Json => {
+            writeln!(writer, "[")?;
+            for (index, symbol) in symbols.iter().enumerate() {
+                let separator = if index + 1 < symbols.len() { "," } else { "" };
+                writeln!(writer, "  {{\"name\": {}, \"kind\": \"{}\", \"address\": {}}}{}",
+                         json_string(&symbol.name), symbol.kind.name(), symbol.address, separator)?;
+            }
+            writeln!(writer, "]")?;
+        }
+        SymbolFormat::Csv => {
+            // identifiers can't hold commas or quotes, so nothing needs quoting
+            writeln!(writer, "name,kind,address")?;
+            for symbol in symbols.iter() {
+                writeln!(writer, "{},{},{}", symbol.name, symbol.kind.name(), symbol.address)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The labels and variables of an assembled program, written next to its
+/// machine code so that a disassembly can put the names back.
+///
+/// The file has one symbol per line, as "kind name address":
+///
+/// ```text
+/// label LOOP 4
+/// variable sum 16
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    /// Label names and their ROM addresses, sorted by address
+    labels: Vec<(String, u16)>,
+    /// Variable names and their RAM addresses, sorted by address
+    variables: Vec<(String, u16)>,
+}
+
+/// Sorts symbols by address, keeping declaration order for symbols at the same address
+fn sorted(mut symbols: Vec<(String, u16)>) -> Vec<(String, u16)> {
+    symbols.sort_by_key(|&(_, address)| address);
+    symbols
+}
+
+impl SymbolMap {
+    pub fn new(labels: Vec<(String, u16)>, variables: Vec<(String, u16)>) -> SymbolMap {
+        SymbolMap {
+            labels: sorted(labels),
+            variables: sorted(variables),
+        }
+    }
+
+    /// Collects the labels and variables of a resolved SymbolTable,
+    /// leaving out the predefined symbols
+    pub fn from_symbol_table(symbol_table: &SymbolTable) -> SymbolMap {
+        let addresses = |names: &[String]| -> Vec<(String, u16)> {
+            names.iter()
+                .map(|name| (name.clone(), symbol_table.symbol_map[name] as u16))
+                .collect()
+        };
+        SymbolMap::new(addresses(&symbol_table.labels), addresses(&symbol_table.variables))
+    }
+
+    /// Reads a symbol map, as written by write. Predefined symbols, which an
+    /// exported symbol table also lists, are skipped.
+    ///
+    /// Arguments:
+    ///
+    /// * reader - the symbol map file
+    /// * file_name - the name of the file, used in errors
+    pub fn read<R: BufRead>(reader: R, file_name: &str) -> Result<SymbolMap, AsmError> {
+        let mut labels = Vec::new();
+        let mut variables = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| AsmError::io(e, file_name))?;
+            let text = line.split("//").next().unwrap().trim();
+            if text.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            let entry = match fields[..] {
+                [kind, name, address] => address.parse::<u16>().ok().and_then(|address| match kind {
+                    "label" => Some((Some(&mut labels), name, address)),
+                    "variable" => Some((Some(&mut variables), name, address)),
+                    "predefined" => Some((None, name, address)),
+                    _ => None,
+                }),
+                _ => None,
+            };
+            match entry {
+                Some((Some(symbols), name, address)) => symbols.push((name.to_string(), address)),
+                Some((None, _, _)) => {}
+                None => {
+                    let start = line.find(text).unwrap_or(0);
+                    return Err(AsmError::InvalidSymbolEntry {
+                        text: text.to_string(),
+                        span: Span::new(file_name, index + 1, start, start + text.len()),
+                    });
+                }
+            }
+        }
+        Ok(SymbolMap::new(labels, variables))
+    }
+
+    /// Writes the symbol map, labels first and then variables, each by address
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (name, address) in self.labels.iter() {
+            writeln!(writer, "label {} {}", name, address)?;
+        }
+        for (name, address) in self.variables.iter() {
+            writeln!(writer, "variable {} {}", name, address)?;
+        }
+        Ok(())
+    }
+
+    /// The labels and their ROM addresses, sorted by address
+    pub fn labels(&self) -> &[(String, u16)] {
+        &self.labels
+    }
+
+    /// The variables and their RAM addresses, sorted by address
+    pub fn variables(&self) -> &[(String, u16)] {
+        &self.variables
+    }
+
+    /// The labels declared at a ROM address
+    pub fn labels_at(&self, address: u16) -> impl Iterator<Item = &str> {
+        self.labels.iter()
+            .filter(move |&(_, label_address)| *label_address == address)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The first label declared at a ROM address
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels_at(address).next()
+    }
+
+    /// The variable allocated at a RAM address
+    pub fn variable_at(&self, address: u16) -> Option<&str> {
+        self.variables.iter()
+            .find(|&(_, variable_address)| *variable_address == address)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Diagnostics;
+
+    fn symbol_map() -> SymbolMap {
+        let mut symbol_table = SymbolTable::with_predefined();
+        let source = "@i\nM=0\n(LOOP)\n@sum\nM=D\n(END)\n@LOOP\n0;JMP\n";
+        symbol_table.resolve(source, "test.asm", &mut Diagnostics::new());
+        SymbolMap::from_symbol_table(&symbol_table)
+    }
+
+    #[test]
+    fn symbols_from_table() {
+        let symbol_map = symbol_map();
+        assert_eq!(symbol_map.label_at(2), Some("LOOP"));
+        assert_eq!(symbol_map.label_at(4), Some("END"));
+        assert_eq!(symbol_map.variable_at(17), Some("sum"));
+        // predefined symbols are left out
+        assert_eq!(symbol_map.variable_at(0), None);
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let mut out = Vec::new();
+        symbol_map().write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out.clone()).unwrap(),
+                   "label LOOP 2\nlabel END 4\nvariable i 16\nvariable sum 17\n");
+        assert_eq!(SymbolMap::read(out.as_slice(), "test.sym").unwrap(), symbol_map());
+    }
+
+    #[test]
+    fn export_symbols() {
+        let mut symbol_table = SymbolTable::with_predefined();
+        symbol_table.resolve("@i\nM=0\n(LOOP)\n@sum\nM=D\n@LOOP\n0;JMP\n", "test.asm", &mut Diagnostics::new());
+        let symbols = symbols(&symbol_table);
+        let find = |name: &str| symbols.iter().position(|symbol| symbol.name == name).unwrap();
+        assert_eq!(symbols[find("LOOP")], Symbol { name: "LOOP".to_string(), kind: SymbolKind::Label, address: 2 });
+        assert_eq!(symbols[find("SCREEN")].kind, SymbolKind::Predefined);
+        // predefined symbols come first at an address, so R2 and LOOP come before R3
+        assert!(find("R2") < find("LOOP") && find("LOOP") < find("R3"));
+        assert!(symbols.windows(2).all(|pair| pair[0].address <= pair[1].address));
+
+        let sample = [symbols[find("R2")].clone(), symbols[find("LOOP")].clone(), symbols[find("sum")].clone()];
+        let write = |format: SymbolFormat| {
+            let mut out = Vec::new();
+            write_symbols(&mut out, &sample, format).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(write(SymbolFormat::Text), "predefined R2 2\nlabel LOOP 2\nvariable sum 17\n");
+        assert_eq!(write(SymbolFormat::Csv), "name,kind,address\nR2,predefined,2\nLOOP,label,2\nsum,variable,17\n");
+        assert_eq!(write(SymbolFormat::Json), "[\n\
+            \x20 {\"name\": \"R2\", \"kind\": \"predefined\", \"address\": 2},\n\
+            \x20 {\"name\": \"LOOP\", \"kind\": \"label\", \"address\": 2},\n\
+            \x20 {\"name\": \"sum\", \"kind\": \"variable\", \"address\": 17}\n]\n");
+
+        // the text export reads back as a symbol map, without the predefined symbols
+        let symbol_map = SymbolMap::read(write(SymbolFormat::Text).as_bytes(), "test.sym").unwrap();
+        assert_eq!(symbol_map.labels(), &[("LOOP".to_string(), 2)]);
+        assert_eq!(symbol_map.variables(), &[("sum".to_string(), 17)]);
+    }
+
+    #[test]
+    fn symbol_format_extensions() {
+        assert_eq!(SymbolFormat::Text.extension(), "sym");
+        for format in [SymbolFormat::Json, SymbolFormat::Csv].iter() {
+            assert_eq!(SymbolFormat::from_name(format.extension()), Some(*format));
+        }
+    }
+
+    #[test]
+    fn read_invalid_entry() {
+        match SymbolMap::read("label LOOP 2\n\nconstant X 3\n".as_bytes(), "test.sym") {
+            Err(AsmError::InvalidSymbolEntry { text, span }) => {
+                assert_eq!(text, "constant X 3");
+                assert_eq!(span.line, 3);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
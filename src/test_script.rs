@@ -0,0 +1,583 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use emulator::{Emulator, ROM_SIZE};
+use error::{AsmError, Span};
+use output::{read_words, OutputFormat};
+use {assemble_source, CDecoder, Diagnostics};
+
+/// A token of a test script, with where it was found
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    start: usize,
+}
+
+impl<'a> Token<'a> {
+    fn span(&self, file_name: &str) -> Span {
+        Span::new(file_name, self.line, self.start, self.start + self.text.len())
+    }
+
+    fn is_terminator(&self) -> bool {
+        self.text == "," || self.text == ";"
+    }
+}
+
+/// Splits a test script into words, quoted strings and the punctuation , ; { },
+/// skipping // and /* */ comments
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut in_comment = false;
+    for (index, text) in source.lines().enumerate() {
+        let mut start = 0;
+        while start < text.len() {
+            let rest = &text[start..];
+            if in_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        in_comment = false;
+                        start += end + 2;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            let c = rest.chars().next().unwrap();
+            let len = if c.is_whitespace() {
+                start += c.len_utf8();
+                continue;
+            } else if rest.starts_with("//") {
+                break;
+            } else if rest.starts_with("/*") {
+                in_comment = true;
+                start += 2;
+                continue;
+            } else if ",;{}".contains(c) {
+                1
+            } else if c == '"' {
+                rest[1..].find('"').map_or(rest.len(), |end| end + 2)
+            } else {
+                rest.find(|c: char| c.is_whitespace() || ",;{}".contains(c))
+                    .into_iter()
+                    .chain(rest.find("//"))
+                    .chain(rest.find("/*"))
+                    .min()
+                    .unwrap_or(rest.len())
+            };
+            tokens.push(Token { text: &rest[..len], line: index + 1, start });
+            start += len;
+        }
+    }
+    tokens
+}
+
+/// A CPU value that a test script can set or output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    A,
+    D,
+    PC,
+    Ram(u16),
+    Rom(u16),
+    /// The number of clock cycles run
+    Time,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Variable> {
+        let indexed = |prefix: &str| -> Option<u16> {
+            let index = name.strip_prefix(prefix)?.strip_suffix(']')?;
+            index.parse::<u16>().ok().filter(|index| (*index as usize) < ROM_SIZE)
+        };
+        match name {
+            "A" => Some(Variable::A),
+            "D" => Some(Variable::D),
+            "PC" => Some(Variable::PC),
+            "time" => Some(Variable::Time),
+            _ => indexed("RAM[").map(Variable::Ram)
+                .or_else(|| indexed("ROM[").map(Variable::Rom))
+                .or_else(|| indexed("ROM32K[").map(Variable::Rom)),
+        }
+    }
+}
+
+/// A column of the output list, such as RAM[0]%D2.6.2: the variable, its
+/// format (B, X, D or S), and the padding left of it, its width and the padding right of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Column {
+    name: String,
+    variable: Variable,
+    format: char,
+    pad_left: usize,
+    width: usize,
+    pad_right: usize,
+}
+
+impl Column {
+    fn parse(token: &Token, file_name: &str) -> Result<Column, AsmError> {
+        let invalid = || AsmError::InvalidScript {
+            message: format!("invalid output column '{}'", token.text),
+            span: token.span(file_name),
+        };
+        let (name, format) = match token.text.find('%') {
+            Some(index) => (&token.text[..index], &token.text[index + 1..]),
+            None => (token.text, "B1.16.1"),
+        };
+        let variable = Variable::from_name(name).ok_or_else(invalid)?;
+        let mut chars = format.chars();
+        let format_char = chars.next().filter(|c| "BXDS".contains(*c)).ok_or_else(invalid)?;
+        let sizes: Vec<usize> = chars.as_str().split('.').map(|n| n.parse()).collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        match sizes[..] {
+            [pad_left, width, pad_right] => Ok(Column {
+                name: name.to_string(),
+                variable,
+                format: format_char,
+                pad_left,
+                width,
+                pad_right,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The column's name, centred in the column
+    fn header(&self) -> String {
+        let total = self.pad_left + self.width + self.pad_right;
+        let name: String = self.name.chars().take(total).collect();
+        let left = (total - name.len()) / 2;
+        format!("{}{}{}", " ".repeat(left), name, " ".repeat(total - left - name.len()))
+    }
+
+    /// Formats a value in the column, right-aligned and cut to its width
+    fn format(&self, value: &str) -> String {
+        let value = match self.format {
+            'D' | 'S' => value.to_string(),
+            _ => {
+                let number: u16 = value.parse::<i32>().map_or(0, |n| n as u16);
+                if self.format == 'B' { format!("{:016b}", number) } else { format!("{:04X}", number) }
+            }
+        };
+        let skip = value.chars().count().saturating_sub(self.width);
+        let value: String = value.chars().skip(skip).collect();
+        format!("{}{:>width$}{}", " ".repeat(self.pad_left), value, " ".repeat(self.pad_right), width = self.width)
+    }
+}
+
+/// A test script command
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    /// Loads a program, or the .hack file named after the script
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(Variable, u16),
+    Repeat(u64, Vec<Command>),
+    Tick,
+    Tock,
+    TickTock,
+    Output,
+    Echo(String),
+    ClearEcho,
+}
+
+/// Parses a number as written in test scripts: decimal, or with a
+/// %D, %X or %B prefix, and possibly negative
+fn parse_number(text: &str) -> Option<u16> {
+    let (radix, digits) = match text.get(..2) {
+        Some("%D") => (10, &text[2..]),
+        Some("%X") => (16, &text[2..]),
+        Some("%B") => (2, &text[2..]),
+        _ => (10, text),
+    };
+    let value = i32::from_str_radix(digits, radix).ok()?;
+    if (-32768..=65535).contains(&value) { Some(value as u16) } else { None }
+}
+
+struct Parser<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    position: usize,
+    file_name: &'b str,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+    fn error(&self, message: String, token: Option<&Token>) -> AsmError {
+        let span = match token.or(self.tokens.last()) {
+            Some(token) => token.span(self.file_name),
+            None => Span::file(self.file_name),
+        };
+        AsmError::InvalidScript { message, span }
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.position)
+    }
+
+    /// Takes the argument of a command, which must not be punctuation
+    fn argument(&mut self, command: &Token) -> Result<Token<'a>, AsmError> {
+        match self.next() {
+            Some(token) if !token.is_terminator() && token.text != "{" && token.text != "}" => Ok(token),
+            other => Err(self.error(format!("'{}' is missing an argument", command.text), other.as_ref())),
+        }
+    }
+
+    /// Parses commands until the end of the script, or the } that closes a repeat block
+    fn parse_block(&mut self, opening: Option<&Token>) -> Result<Vec<Command>, AsmError> {
+        let mut commands = Vec::new();
+        loop {
+            let token = match self.next() {
+                Some(token) => token,
+                None if opening.is_some() => return Err(self.error("missing '}'".to_string(), opening)),
+                None => return Ok(commands),
+            };
+            let command = match token.text {
+                "}" if opening.is_some() => return Ok(commands),
+                "," | ";" => continue,
+                "repeat" => {
+                    let count = self.argument(&token)?;
+                    let count_value = count.text.parse::<u64>()
+                        .map_err(|_| self.error(format!("invalid repeat count '{}'", count.text), Some(&count)))?;
+                    match self.next() {
+                        Some(ref brace) if brace.text == "{" => {
+                            let body = self.parse_block(Some(brace))?;
+                            commands.push(Command::Repeat(count_value, body));
+                            continue;
+                        }
+                        other => return Err(self.error("expected '{' after the repeat count".to_string(), other.as_ref())),
+                    }
+                }
+                "load" => match self.peek() {
+                    Some(next) if !next.is_terminator() => Command::Load(Some(self.argument(&token)?.text.to_string())),
+                    _ => Command::Load(None),
+                },
+                "output-file" => Command::OutputFile(self.argument(&token)?.text.to_string()),
+                "compare-to" => Command::CompareTo(self.argument(&token)?.text.to_string()),
+                "output-list" => {
+                    let mut columns = Vec::new();
+                    while let Some(next) = self.peek().filter(|next| !next.is_terminator()).cloned() {
+                        self.position += 1;
+                        columns.push(Column::parse(&next, self.file_name)?);
+                    }
+                    Command::OutputList(columns)
+                }
+                "set" => {
+                    let name = self.argument(&token)?;
+                    let variable = match Variable::from_name(name.text) {
+                        Some(Variable::Rom(_)) | Some(Variable::Time) | None => {
+                            return Err(self.error(format!("cannot set '{}'", name.text), Some(&name)));
+                        }
+                        Some(variable) => variable,
+                    };
+                    let value = self.argument(&token)?;
+                    let number = parse_number(value.text)
+                        .ok_or_else(|| self.error(format!("invalid value '{}'", value.text), Some(&value)))?;
+                    Command::Set(variable, number)
+                }
+                "tick" => Command::Tick,
+                "tock" => Command::Tock,
+                "ticktock" => Command::TickTock,
+                "output" => Command::Output,
+                "echo" => Command::Echo(self.argument(&token)?.text.trim_matches('"').to_string()),
+                "clear-echo" => Command::ClearEcho,
+                _ => return Err(self.error(format!("unknown command '{}'", token.text), Some(&token))),
+            };
+            match self.next() {
+                Some(ref next) if next.is_terminator() => commands.push(command),
+                other => {
+                    let message = format!("expected ',' or ';' after '{}'", token.text);
+                    return Err(self.error(message, other.as_ref()));
+                }
+            }
+        }
+    }
+}
+
+/// A parsed nand2tetris CPU test script (.tst), as run by the CPU emulator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestScript {
+    name: String,
+    commands: Vec<Command>,
+}
+
+/// The result of running a test script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    /// The output lines, starting with the output-list header
+    pub lines: Vec<String>,
+    /// The file named by output-file, relative to the script
+    pub output_file: Option<PathBuf>,
+    /// The file named by compare-to, relative to the script
+    pub compare_to: Option<PathBuf>,
+    /// The messages of echo commands
+    pub echoes: Vec<String>,
+}
+
+impl Execution {
+    /// The output as it's written to the .out file
+    pub fn output(&self) -> String {
+        self.lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// Compares the output against the expected output, line by line
+    ///
+    /// Returns: the first line that differs
+    pub fn compare(&self, expected: &str, file_name: &str) -> Result<(), AsmError> {
+        let expected: Vec<&str> = expected.lines().map(|line| line.trim_end()).collect();
+        for index in 0..self.lines.len().max(expected.len()) {
+            let actual = self.lines.get(index).map_or("", |line| line.trim_end());
+            let wanted = expected.get(index).cloned().unwrap_or("");
+            if actual != wanted {
+                return Err(AsmError::ComparisonFailure {
+                    expected: wanted.to_string(),
+                    actual: actual.to_string(),
+                    span: Span::new(file_name, index + 1, 0, wanted.len()),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of a running test script
+struct Runner<'a> {
+    dir: &'a Path,
+    name: &'a str,
+    emulator: Emulator,
+    /// Whether the clock is between a tick and a tock
+    ticked: bool,
+    columns: Vec<Column>,
+    execution: Execution,
+}
+
+impl<'a> Runner<'a> {
+    fn load(&mut self, program: Option<&str>) -> Result<(), AsmError> {
+        let path = match program {
+            Some(program) => self.dir.join(program),
+            None => self.dir.join(self.name).with_extension("hack"),
+        };
+        let file_name = path.display().to_string();
+        let words = if path.extension().is_some_and(|extension| extension == "asm") {
+            let source = fs::read_to_string(&path).map_err(|e| AsmError::io(e, &file_name))?;
+            let mut diagnostics = Diagnostics::new();
+            let assembly = assemble_source(&source, &file_name, &CDecoder::default(), &mut diagnostics);
+            if let Some(error) = diagnostics.into_errors().into_iter().next() {
+                return Err(error);
+            }
+            assembly.words
+        } else {
+            let file = File::open(&path).map_err(|e| AsmError::io(e, &file_name))?;
+            read_words(BufReader::new(file), OutputFormat::Hack, &file_name)?
+        };
+        self.emulator = Emulator::new(&words, &file_name)?;
+        self.ticked = false;
+        Ok(())
+    }
+
+    fn value(&self, variable: Variable) -> String {
+        let emulator = &self.emulator;
+        let word = match variable {
+            Variable::A => emulator.a(),
+            Variable::D => emulator.d(),
+            Variable::PC => emulator.pc(),
+            Variable::Ram(address) => emulator.peek(address),
+            Variable::Rom(address) => emulator.rom()[address as usize],
+            Variable::Time => {
+                return format!("{}{}", emulator.cycles(), if self.ticked { "+" } else { "" });
+            }
+        };
+        (word as i16).to_string()
+    }
+
+    fn execute(&mut self, commands: &[Command]) -> Result<(), AsmError> {
+        for command in commands.iter() {
+            match *command {
+                Command::Load(ref program) => self.load(program.as_deref())?,
+                Command::OutputFile(ref file) => self.execution.output_file = Some(self.dir.join(file)),
+                Command::CompareTo(ref file) => self.execution.compare_to = Some(self.dir.join(file)),
+                Command::OutputList(ref columns) => {
+                    self.columns = columns.clone();
+                    let headers: Vec<String> = self.columns.iter().map(|column| column.header()).collect();
+                    self.execution.lines.push(format!("|{}|", headers.join("|")));
+                }
+                Command::Set(variable, value) => match variable {
+                    Variable::A => self.emulator.set_a(value),
+                    Variable::D => self.emulator.set_d(value),
+                    Variable::PC => self.emulator.set_pc(value),
+                    Variable::Ram(address) => self.emulator.poke(address, value),
+                    Variable::Rom(_) | Variable::Time => unreachable!("rejected by the parser"),
+                },
+                Command::Repeat(count, ref body) => {
+                    for _ in 0..count {
+                        self.execute(body)?;
+                    }
+                }
+                Command::Tick => self.ticked = true,
+                Command::Tock | Command::TickTock => {
+                    self.emulator.step();
+                    self.ticked = false;
+                }
+                Command::Output => {
+                    let values: Vec<String> = self.columns.iter()
+                        .map(|column| column.format(&self.value(column.variable)))
+                        .collect();
+                    self.execution.lines.push(format!("|{}|", values.join("|")));
+                }
+                Command::Echo(ref message) => self.execution.echoes.push(message.clone()),
+                Command::ClearEcho => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TestScript {
+    /// Parses a test script
+    ///
+    /// Arguments:
+    ///
+    /// * source - the text of the script
+    /// * file_name - the name of the script, used in errors and as the default program to load
+    pub fn parse(source: &str, file_name: &str) -> Result<TestScript, AsmError> {
+        let tokens = tokenize(source);
+        let mut parser = Parser { tokens: &tokens, position: 0, file_name };
+        let commands = parser.parse_block(None)?;
+        let name = Path::new(file_name).file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        Ok(TestScript { name, commands })
+    }
+
+    /// Runs the script without writing or comparing its output
+    ///
+    /// Arguments:
+    ///
+    /// * dir - the directory that the files named in the script are relative to
+    pub fn execute(&self, dir: &Path) -> Result<Execution, AsmError> {
+        let mut runner = Runner {
+            dir,
+            name: &self.name,
+            emulator: Emulator::new(&[], &self.name)?,
+            ticked: false,
+            columns: Vec::new(),
+            execution: Execution {
+                lines: Vec::new(),
+                output_file: None,
+                compare_to: None,
+                echoes: Vec::new(),
+            },
+        };
+        runner.execute(&self.commands)?;
+        Ok(runner.execution)
+    }
+}
+
+/// Runs a test script file the way the nand2tetris CPU emulator does: writes
+/// the output-file, then compares it against the compare-to file
+///
+/// Returns: the execution, or the first line that differs from the compare-to file
+pub fn run_test_script(path: &Path) -> Result<Execution, AsmError> {
+    let execution = execute_test_script(path)?;
+    if let Some(ref output_file) = execution.output_file {
+        fs::write(output_file, execution.output()).map_err(|e| AsmError::io(e, &output_file.display().to_string()))?;
+    }
+    compare_to_file(&execution)?;
+    Ok(execution)
+}
+
+/// Reads and runs a test script file, keeping its output in memory
+fn execute_test_script(path: &Path) -> Result<Execution, AsmError> {
+    let file_name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| AsmError::io(e, &file_name))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    TestScript::parse(&source, &file_name)?.execute(dir)
+}
+
+/// Compares the output of an execution against its compare-to file, if it has one
+fn compare_to_file(execution: &Execution) -> Result<(), AsmError> {
+    if let Some(ref compare_to) = execution.compare_to {
+        let compare_name = compare_to.display().to_string();
+        let expected = fs::read_to_string(compare_to).map_err(|e| AsmError::io(e, &compare_name))?;
+        execution.compare(&expected, &compare_name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_scripts() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_scripts")
+    }
+
+    #[test]
+    fn tokenize_script() {
+        let tokens = tokenize("set RAM[0] 3, /* a\ncomment */ repeat 2 {ticktock;} // done\necho \"a b\";");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(texts, vec!["set", "RAM[0]", "3", ",", "repeat", "2", "{", "ticktock", ";", "}", "echo", "\"a b\"", ";"]);
+        assert_eq!((tokens[4].line, tokens[4].start), (2, 11));
+    }
+
+    #[test]
+    fn parse_commands() {
+        let script = TestScript::parse("load Max.asm, set RAM[1] %X7FFF; repeat 3 { ticktock; } output;", "Max.tst").unwrap();
+        assert_eq!(script.commands, vec![
+            Command::Load(Some("Max.asm".to_string())),
+            Command::Set(Variable::Ram(1), 0x7fff),
+            Command::Repeat(3, vec![Command::TickTock]),
+            Command::Output,
+        ]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |source: &str| TestScript::parse(source, "test.tst").unwrap_err().to_string();
+        assert_eq!(error("set RAM[0] 2"), "test.tst:1:12: expected ',' or ';' after 'set'");
+        assert_eq!(error("repeat 2 { ticktock;"), "test.tst:1:10: missing '}'");
+        assert_eq!(error("set KBD[0] 1;"), "test.tst:1:5: cannot set 'KBD[0]'");
+        assert_eq!(error("tickle;"), "test.tst:1:1: unknown command 'tickle'");
+        assert_eq!(error("output-list RAM[0]%Q1.2.3;"), "test.tst:1:13: invalid output column 'RAM[0]%Q1.2.3'");
+    }
+
+    #[test]
+    fn format_columns() {
+        let token = |text| Token { text, line: 1, start: 0 };
+        let column = Column::parse(&token("RAM[0]%D2.6.2"), "test.tst").unwrap();
+        assert_eq!(column.header(), "  RAM[0]  ");
+        assert_eq!(column.format("-5"), "      -5  ");
+        let column = Column::parse(&token("A"), "test.tst").unwrap();
+        assert_eq!(column.format("-1"), " 1111111111111111 ");
+        let column = Column::parse(&token("D%X1.4.1"), "test.tst").unwrap();
+        assert_eq!(column.format("255"), " 00FF ");
+    }
+
+    #[test]
+    fn run_max_test_script() {
+        // the output is compared in memory, rather than written into the source tree
+        let execution = execute_test_script(&test_scripts().join("Max.tst")).unwrap();
+        assert_eq!(execution.output_file, Some(test_scripts().join("Max.out")));
+        compare_to_file(&execution).unwrap();
+        assert_eq!(execution.lines.len(), 4);
+        assert_eq!(execution.lines[1], "|       0  |       0  |       0  |");
+    }
+
+    #[test]
+    fn comparison_failure() {
+        let execution = TestScript::parse("load Max.asm, output-list RAM[2]%D1.6.1; set RAM[0] 4, repeat 20 { ticktock; } output;",
+                                          "Max.tst").unwrap().execute(&test_scripts()).unwrap();
+        assert_eq!(execution.output(), "| RAM[2] |\n|      4 |\n");
+        match execution.compare("| RAM[2] |\n|      5 |\n", "Max.cmp") {
+            Err(AsmError::ComparisonFailure { expected, actual, span }) => {
+                assert_eq!((expected.as_str(), actual.as_str(), span.line), ("|      5 |", "|      4 |", 2));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,633 @@
+use std::fmt;
+use std::fmt::Write;
+use std::path::Path;
+
+use diagnostics::Diagnostics;
+use error::{AsmError, SourceLine, Span};
+use lexer::is_identifier;
+use is_predefined;
+
+/// A memory segment of the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Argument,
+    Local,
+    Static,
+    Constant,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+impl Segment {
+    pub fn from_name(name: &str) -> Option<Segment> {
+        match name {
+            "argument" => Some(Segment::Argument),
+            "local" => Some(Segment::Local),
+            "static" => Some(Segment::Static),
+            "constant" => Some(Segment::Constant),
+            "this" => Some(Segment::This),
+            "that" => Some(Segment::That),
+            "pointer" => Some(Segment::Pointer),
+            "temp" => Some(Segment::Temp),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Segment::Argument => "argument",
+            Segment::Local => "local",
+            Segment::Static => "static",
+            Segment::Constant => "constant",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+        }
+    }
+
+    /// The largest index the segment allows
+    fn max_index(&self) -> u16 {
+        match *self {
+            Segment::Pointer => 1,
+            Segment::Temp => 7,
+            Segment::Static => 239,
+            _ => 32767,
+        }
+    }
+}
+
+/// An arithmetic or logical command, which works on the top of the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+impl ArithmeticOp {
+    pub fn from_name(name: &str) -> Option<ArithmeticOp> {
+        match name {
+            "add" => Some(ArithmeticOp::Add),
+            "sub" => Some(ArithmeticOp::Sub),
+            "neg" => Some(ArithmeticOp::Neg),
+            "eq" => Some(ArithmeticOp::Eq),
+            "gt" => Some(ArithmeticOp::Gt),
+            "lt" => Some(ArithmeticOp::Lt),
+            "and" => Some(ArithmeticOp::And),
+            "or" => Some(ArithmeticOp::Or),
+            "not" => Some(ArithmeticOp::Not),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ArithmeticOp::Add => "add",
+            ArithmeticOp::Sub => "sub",
+            ArithmeticOp::Neg => "neg",
+            ArithmeticOp::Eq => "eq",
+            ArithmeticOp::Gt => "gt",
+            ArithmeticOp::Lt => "lt",
+            ArithmeticOp::And => "and",
+            ArithmeticOp::Or => "or",
+            ArithmeticOp::Not => "not",
+        }
+    }
+}
+
+/// A parsed line of a .vm file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmCommand {
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Arithmetic(ArithmeticOp),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    /// function name locals
+    Function(String, u16),
+    /// call name arguments
+    Call(String, u16),
+    Return,
+}
+
+impl fmt::Display for VmCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmCommand::Push(segment, index) => write!(f, "push {} {}", segment.name(), index),
+            VmCommand::Pop(segment, index) => write!(f, "pop {} {}", segment.name(), index),
+            VmCommand::Arithmetic(op) => f.write_str(op.name()),
+            VmCommand::Label(ref label) => write!(f, "label {}", label),
+            VmCommand::Goto(ref label) => write!(f, "goto {}", label),
+            VmCommand::IfGoto(ref label) => write!(f, "if-goto {}", label),
+            VmCommand::Function(ref name, locals) => write!(f, "function {} {}", name, locals),
+            VmCommand::Call(ref name, arguments) => write!(f, "call {} {}", name, arguments),
+            VmCommand::Return => f.write_str("return"),
+        }
+    }
+}
+
+/// Parses a line of a .vm file into a command
+///
+/// Returns: the command on the line, or None if the line is blank or only a comment
+pub fn parse_vm_line(line: &SourceLine) -> Result<Option<VmCommand>, AsmError> {
+    let code = line.text.split("//").next().unwrap().trim();
+    let fields: Vec<&str> = code.split_whitespace().collect();
+    let command = match fields.first() {
+        Some(command) => *command,
+        None => return Ok(None),
+    };
+    let error = |message: String, token: &str| AsmError::InvalidVmCommand {
+        message,
+        span: line.span_of(token),
+    };
+    let expected_arguments = match command {
+        "push" | "pop" | "function" | "call" => 2,
+        "label" | "goto" | "if-goto" => 1,
+        _ => 0,
+    };
+    if let Some(extra) = fields.get(expected_arguments + 1) {
+        return Err(error(format!("unexpected '{}' after '{}' command", extra, command), extra));
+    }
+    if fields.len() <= expected_arguments {
+        return Err(error(format!("'{}' needs {} argument{}", command, expected_arguments,
+                                 if expected_arguments == 1 { "" } else { "s" }), code));
+    }
+    let number = |text: &str, max: u16| -> Result<u16, AsmError> {
+        match text.parse::<u16>() {
+            Ok(value) if value <= max => Ok(value),
+            _ => Err(error(format!("'{}' is not a number between 0 and {}", text, max), text)),
+        }
+    };
+    let name = |text: &str| -> Result<String, AsmError> {
+        if !is_identifier(text) {
+            Err(error(format!("'{}' is not a valid name", text), text))
+        } else if text.contains('$') {
+            // generated labels use '$', so that they can't clash with the program's
+            Err(error(format!("'{}' contains '$', which is reserved for generated labels", text), text))
+        } else {
+            Ok(text.to_string())
+        }
+    };
+    // a function's label would be shadowed by a predefined symbol of the same name
+    let function_name = |text: &str| -> Result<String, AsmError> {
+        let name = name(text)?;
+        if is_predefined(&name) {
+            return Err(error(format!("function '{}' has the name of a predefined symbol", name), text));
+        }
+        Ok(name)
+    };
+    let command = match command {
+        "push" | "pop" => {
+            let segment = Segment::from_name(fields[1])
+                .ok_or_else(|| error(format!("unknown segment '{}'", fields[1]), fields[1]))?;
+            let index = number(fields[2], segment.max_index())?;
+            if command == "push" {
+                VmCommand::Push(segment, index)
+            } else if segment == Segment::Constant {
+                return Err(error("cannot pop to the constant segment".to_string(), fields[1]));
+            } else {
+                VmCommand::Pop(segment, index)
+            }
+        }
+        "label" => VmCommand::Label(name(fields[1])?),
+        "goto" => VmCommand::Goto(name(fields[1])?),
+        "if-goto" => VmCommand::IfGoto(name(fields[1])?),
+        "function" => VmCommand::Function(function_name(fields[1])?, number(fields[2], 32767)?),
+        "call" => VmCommand::Call(function_name(fields[1])?, number(fields[2], 32767)?),
+        "return" => VmCommand::Return,
+        _ => match ArithmeticOp::from_name(command) {
+            Some(op) => VmCommand::Arithmetic(op),
+            None => return Err(error(format!("unknown VM command '{}'", command), command)),
+        },
+    };
+    Ok(Some(command))
+}
+
+/// Generates Hack assembly for VM commands
+pub struct VmTranslator {
+    asm: String,
+    /// The name of the file being translated, without its extension, which prefixes its statics
+    file_stem: String,
+    /// The function being translated, which scopes its labels
+    function: String,
+    /// Makes the labels generated for comparisons and return addresses unique
+    label_count: usize,
+}
+
+impl Default for VmTranslator {
+    fn default() -> VmTranslator {
+        VmTranslator::new()
+    }
+}
+
+impl VmTranslator {
+    pub fn new() -> VmTranslator {
+        VmTranslator {
+            asm: String::new(),
+            file_stem: String::new(),
+            function: String::new(),
+            label_count: 0,
+        }
+    }
+
+    /// Appends lines of assembly
+    fn emit(&mut self, lines: &[&str]) {
+        for line in lines.iter() {
+            self.asm.push_str(line);
+            self.asm.push('\n');
+        }
+    }
+
+    /// Appends lines of assembly built with format!
+    fn emit_owned(&mut self, lines: &[String]) {
+        for line in lines.iter() {
+            self.asm.push_str(line);
+            self.asm.push('\n');
+        }
+    }
+
+    /// Generates a label for a comparison or return address, as function$kind$n.
+    /// VM names can't contain '$', so the second '$' keeps it apart from the
+    /// program's own labels.
+    fn unique_label(&mut self, kind: &str) -> String {
+        self.label_count += 1;
+        let scope = if self.function.is_empty() { &self.file_stem } else { &self.function };
+        format!("{}${}${}", scope, kind, self.label_count)
+    }
+
+    /// Labels declared in a function are scoped to it, as function$label
+    fn scoped_label(&self, label: &str) -> String {
+        let scope = if self.function.is_empty() { &self.file_stem } else { &self.function };
+        format!("{}${}", scope, label)
+    }
+
+    /// Pushes the value of D
+    fn push_d(&mut self) {
+        self.emit(&["@SP", "A=M", "M=D", "@SP", "M=M+1"]);
+    }
+
+    /// Pops the top of the stack into D
+    fn pop_d(&mut self) {
+        self.emit(&["@SP", "AM=M-1", "D=M"]);
+    }
+
+    /// The base pointer symbol of a segment that is addressed through one
+    fn base_pointer(segment: Segment) -> Option<&'static str> {
+        match segment {
+            Segment::Local => Some("LCL"),
+            Segment::Argument => Some("ARG"),
+            Segment::This => Some("THIS"),
+            Segment::That => Some("THAT"),
+            _ => None,
+        }
+    }
+
+    /// The symbol or address of a segment entry that has a fixed address
+    fn fixed_address(&self, segment: Segment, index: u16) -> String {
+        match segment {
+            Segment::Static => format!("{}.{}", self.file_stem, index),
+            Segment::Pointer => (3 + index).to_string(),
+            _ => (5 + index).to_string(),
+        }
+    }
+
+    fn push(&mut self, segment: Segment, index: u16) {
+        match (segment, VmTranslator::base_pointer(segment)) {
+            (Segment::Constant, _) => self.emit_owned(&[format!("@{}", index), "D=A".to_string()]),
+            (_, Some(base)) => self.emit_owned(&[
+                format!("@{}", index),
+                "D=A".to_string(),
+                format!("@{}", base),
+                "A=D+M".to_string(),
+                "D=M".to_string(),
+            ]),
+            _ => {
+                let address = self.fixed_address(segment, index);
+                self.emit_owned(&[format!("@{}", address), "D=M".to_string()]);
+            }
+        }
+        self.push_d();
+    }
+
+    fn pop(&mut self, segment: Segment, index: u16) {
+        match VmTranslator::base_pointer(segment) {
+            Some(base) => {
+                // keep the target address in R13 while popping
+                self.emit_owned(&[
+                    format!("@{}", index),
+                    "D=A".to_string(),
+                    format!("@{}", base),
+                    "D=D+M".to_string(),
+                    "@R13".to_string(),
+                    "M=D".to_string(),
+                ]);
+                self.pop_d();
+                self.emit(&["@R13", "A=M", "M=D"]);
+            }
+            None => {
+                self.pop_d();
+                let address = self.fixed_address(segment, index);
+                self.emit_owned(&[format!("@{}", address), "M=D".to_string()]);
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, op: ArithmeticOp) {
+        let comp = match op {
+            ArithmeticOp::Neg => return self.emit(&["@SP", "A=M-1", "M=-M"]),
+            ArithmeticOp::Not => return self.emit(&["@SP", "A=M-1", "M=!M"]),
+            ArithmeticOp::Add => "M=D+M",
+            ArithmeticOp::Sub => "M=M-D",
+            ArithmeticOp::And => "M=D&M",
+            ArithmeticOp::Or => "M=D|M",
+            ArithmeticOp::Eq | ArithmeticOp::Gt | ArithmeticOp::Lt => {
+                let jump = match op {
+                    ArithmeticOp::Eq => "JEQ",
+                    ArithmeticOp::Gt => "JGT",
+                    _ => "JLT",
+                };
+                // assume true, and overwrite with false if the jump isn't taken
+                let done = self.unique_label(op.name());
+                self.pop_d();
+                self.emit(&["A=A-1", "D=M-D", "M=-1"]);
+                self.emit_owned(&[format!("@{}", done), format!("D;{}", jump)]);
+                self.emit(&["@SP", "A=M-1", "M=0"]);
+                self.emit_owned(&[format!("({})", done)]);
+                return;
+            }
+        };
+        self.pop_d();
+        self.emit(&["A=A-1", comp]);
+    }
+
+    fn call(&mut self, function: &str, arguments: u16) {
+        let return_address = self.unique_label("ret");
+        self.emit_owned(&[format!("@{}", return_address), "D=A".to_string()]);
+        self.push_d();
+        for pointer in ["LCL", "ARG", "THIS", "THAT"].iter() {
+            self.emit_owned(&[format!("@{}", pointer), "D=M".to_string()]);
+            self.push_d();
+        }
+        // ARG = SP - 5 - arguments, LCL = SP
+        self.emit_owned(&[
+            "@SP".to_string(),
+            "D=M".to_string(),
+            format!("@{}", arguments as u32 + 5),
+            "D=D-A".to_string(),
+            "@ARG".to_string(),
+            "M=D".to_string(),
+        ]);
+        self.emit(&["@SP", "D=M", "@LCL", "M=D"]);
+        self.emit_owned(&[format!("@{}", function), "0;JMP".to_string(), format!("({})", return_address)]);
+    }
+
+    fn function(&mut self, name: &str, locals: u16) {
+        self.function = name.to_string();
+        self.emit_owned(&[format!("({})", name)]);
+        for _ in 0..locals {
+            self.emit(&["@SP", "A=M", "M=0", "@SP", "M=M+1"]);
+        }
+    }
+
+    fn return_from_function(&mut self) {
+        // R13 = frame, R14 = return address, which the return value may overwrite
+        self.emit(&["@LCL", "D=M", "@R13", "M=D", "@5", "A=D-A", "D=M", "@R14", "M=D"]);
+        self.pop_d();
+        self.emit(&["@ARG", "A=M", "M=D", "@ARG", "D=M+1", "@SP", "M=D"]);
+        for pointer in ["THAT", "THIS", "ARG", "LCL"].iter() {
+            self.emit(&["@R13", "AM=M-1", "D=M"]);
+            self.emit_owned(&[format!("@{}", pointer), "M=D".to_string()]);
+        }
+        self.emit(&["@R14", "A=M", "0;JMP"]);
+    }
+
+    /// Generates the assembly for a command, preceded by the command as a comment
+    pub fn translate(&mut self, command: &VmCommand) {
+        let _ = writeln!(self.asm, "// {}", command);
+        match *command {
+            VmCommand::Push(segment, index) => self.push(segment, index),
+            VmCommand::Pop(segment, index) => self.pop(segment, index),
+            VmCommand::Arithmetic(op) => self.arithmetic(op),
+            VmCommand::Label(ref label) => {
+                let label = self.scoped_label(label);
+                self.emit_owned(&[format!("({})", label)]);
+            }
+            VmCommand::Goto(ref label) => {
+                let label = self.scoped_label(label);
+                self.emit_owned(&[format!("@{}", label), "0;JMP".to_string()]);
+            }
+            VmCommand::IfGoto(ref label) => {
+                let label = self.scoped_label(label);
+                self.pop_d();
+                self.emit_owned(&[format!("@{}", label), "D;JNE".to_string()]);
+            }
+            VmCommand::Function(ref name, locals) => self.function(name, locals),
+            VmCommand::Call(ref name, arguments) => self.call(name, arguments),
+            VmCommand::Return => self.return_from_function(),
+        }
+    }
+
+    /// Starts translating a new .vm file, whose name prefixes its statics
+    pub fn start_file(&mut self, file_name: &str) {
+        self.file_stem = Path::new(file_name).file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        self.function.clear();
+    }
+
+    /// Generates the bootstrap code: SP = 256, then call Sys.init
+    pub fn bootstrap(&mut self) {
+        self.asm.push_str("// bootstrap\n");
+        self.emit(&["@256", "D=A", "@SP", "M=D"]);
+        self.file_stem = "Sys".to_string();
+        self.translate(&VmCommand::Call("Sys.init".to_string(), 0));
+    }
+
+    /// Returns the generated assembly
+    pub fn into_assembly(self) -> String {
+        self.asm
+    }
+}
+
+/// Hack assembly translated from a VM program, and where each line of it came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Translation {
+    pub asm: String,
+    /// For each line of `asm`, the VM command it was generated for and the
+    /// command's text. The bootstrap code is attributed to the declaration of Sys.init.
+    pub origins: Vec<(Span, String)>,
+}
+
+/// Translates a program's .vm files into Hack assembly. The bootstrap code is
+/// generated when the program defines Sys.init, as full programs do.
+///
+/// Arguments:
+///
+/// * files - the name and source of each .vm file
+/// * diagnostics - collects the errors in the files
+///
+/// Returns: the assembly, which is incomplete if any errors were found
+pub fn translate_vm(files: &[(&str, &str)], diagnostics: &mut Diagnostics) -> String {
+    translate_vm_program(files, diagnostics).asm
+}
+
+/// Translates a program's .vm files into Hack assembly, like translate_vm, and
+/// also records the VM command that each line of assembly was generated for
+pub fn translate_vm_program(files: &[(&str, &str)], diagnostics: &mut Diagnostics) -> Translation {
+    let mut parsed = Vec::new();
+    for &(file_name, source) in files.iter() {
+        let mut commands = Vec::new();
+        for (index, text) in source.lines().enumerate() {
+            match parse_vm_line(&SourceLine::new(file_name, index + 1, text)) {
+                Ok(Some(command)) => {
+                    let code = text.split("//").next().unwrap().trim();
+                    let start = text.find(code).unwrap_or(0);
+                    let span = Span::new(file_name, index + 1, start, start + code.len());
+                    commands.push((command, (span, code.to_string())));
+                }
+                Ok(None) => {}
+                Err(e) => diagnostics.push(e),
+            }
+        }
+        parsed.push((file_name, commands));
+    }
+
+    let mut translator = VmTranslator::new();
+    let mut origins = Vec::new();
+    let mut attributed = 0;
+    // attributes the lines generated since the last call to a command
+    let mut attribute = |translator: &VmTranslator, origin: &(Span, String)| {
+        let lines = translator.asm[attributed..].matches('\n').count();
+        attributed = translator.asm.len();
+        origins.extend((0..lines).map(|_| origin.clone()));
+    };
+    let sys_init = parsed.iter()
+        .flat_map(|(_, commands)| commands.iter())
+        .find(|&(command, _)| matches!(*command, VmCommand::Function(ref name, _) if name == "Sys.init"));
+    if let Some((_, origin)) = sys_init {
+        translator.bootstrap();
+        attribute(&translator, origin);
+    }
+    for (file_name, commands) in parsed.iter() {
+        translator.start_file(file_name);
+        for (command, origin) in commands.iter() {
+            translator.translate(command);
+            attribute(&translator, origin);
+        }
+    }
+    Translation { asm: translator.into_assembly(), origins }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::Emulator;
+    use assemble;
+
+    fn vm_line(text: &str) -> Result<Option<VmCommand>, AsmError> {
+        parse_vm_line(&SourceLine::new("Test.vm", 1, text))
+    }
+
+    /// Translates and runs a program, with SP at 256 unless it bootstraps itself
+    fn run(files: &[(&str, &str)], cycles: u64) -> Emulator {
+        let mut diagnostics = Diagnostics::new();
+        let asm = translate_vm(files, &mut diagnostics);
+        assert!(!diagnostics.has_errors(), "{:?}", diagnostics.errors());
+        let mut emulator = Emulator::new(&assemble(&asm).unwrap(), "Test.asm").unwrap();
+        emulator.poke(0, 256);
+        emulator.run(cycles);
+        emulator
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(vm_line("  push constant 7 // seven").unwrap(), Some(VmCommand::Push(Segment::Constant, 7)));
+        assert_eq!(vm_line("if-goto LOOP_START").unwrap(), Some(VmCommand::IfGoto("LOOP_START".to_string())));
+        assert_eq!(vm_line("function Main.fib 2").unwrap(), Some(VmCommand::Function("Main.fib".to_string(), 2)));
+        assert_eq!(vm_line("lt").unwrap(), Some(VmCommand::Arithmetic(ArithmeticOp::Lt)));
+        assert_eq!(vm_line("// comment").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let message = |text: &str| vm_line(text).unwrap_err().to_string();
+        assert_eq!(message("push heap 1"), "Test.vm:1:6: unknown segment 'heap'");
+        assert_eq!(message("pop constant 1"), "Test.vm:1:5: cannot pop to the constant segment");
+        assert_eq!(message("push temp 8"), "Test.vm:1:11: '8' is not a number between 0 and 7");
+        assert_eq!(message("add 1"), "Test.vm:1:5: unexpected '1' after 'add' command");
+        assert_eq!(message("goto"), "Test.vm:1:1: 'goto' needs 1 argument");
+        assert_eq!(message("label 1st"), "Test.vm:1:7: '1st' is not a valid name");
+        assert_eq!(message("mul"), "Test.vm:1:1: unknown VM command 'mul'");
+        assert_eq!(message("label a$b"), "Test.vm:1:7: 'a$b' contains '$', which is reserved for generated labels");
+        assert_eq!(message("function SCREEN 0"), "Test.vm:1:10: function 'SCREEN' has the name of a predefined symbol");
+        assert_eq!(message("call R13 0"), "Test.vm:1:6: function 'R13' has the name of a predefined symbol");
+        assert!(vm_line("function R16 0").is_ok());
+    }
+
+    #[test]
+    fn stack_arithmetic() {
+        let source = "push constant 7\npush constant 8\nadd\n\
+                      push constant 5\npush constant 9\nlt\n\
+                      push constant 3\npush constant 3\neq\n\
+                      push constant 2\npush constant 3\ngt\n\
+                      push constant 12\nneg\n\
+                      push constant 10\npush constant 6\nand\nnot\n";
+        let emulator = run(&[("Test.vm", source)], 1000);
+        assert_eq!(emulator.peek(0), 262);
+        let stack: Vec<i16> = emulator.ram()[256..262].iter().map(|word| *word as i16).collect();
+        assert_eq!(stack, vec![15, -1, -1, 0, -12, !(10 & 6)]);
+    }
+
+    #[test]
+    fn memory_segments() {
+        let source = "push constant 3030\npop pointer 0\npush constant 3040\npop pointer 1\n\
+                      push constant 32\npop this 2\npush constant 46\npop that 6\n\
+                      push constant 510\npop temp 6\npush constant 77\npop static 3\n\
+                      push this 2\npush that 6\nadd\npush temp 6\nsub\npush static 3\nadd\n";
+        let emulator = run(&[("Test.vm", source)], 1000);
+        assert_eq!((emulator.peek(3), emulator.peek(4)), (3030, 3040));
+        assert_eq!((emulator.peek(3032), emulator.peek(3046), emulator.peek(11)), (32, 46, 510));
+        assert_eq!(emulator.peek(16), 77);
+        assert_eq!(emulator.peek(256) as i16, 32 + 46 - 510 + 77);
+    }
+
+    #[test]
+    fn labels_apart_from_generated_labels() {
+        // ret.1 and EQ.2 are what the call and eq in A.f would be named, if not for the '$'
+        let source = "function A.f 0\nlabel ret.1\ncall A.g 0\nlabel EQ.2\npush constant 1\npush constant 1\neq\n\
+                      label END\ngoto END\nfunction A.g 0\npush constant 7\nreturn\n";
+        let mut diagnostics = Diagnostics::new();
+        let asm = translate_vm(&[("A.vm", source)], &mut diagnostics);
+        assert!(!diagnostics.has_errors());
+        assert!(asm.contains("(A.f$ret.1)") && asm.contains("(A.f$ret$1)"), "{}", asm);
+        let mut emulator = Emulator::new(&assemble(&asm).unwrap(), "Test.asm").unwrap();
+        emulator.poke(0, 256);
+        emulator.poke(1, 256);
+        emulator.run(1000);
+        // A.g returned 7, then eq pushed true
+        assert_eq!(&emulator.ram()[256..258], &[7, 0xffff]);
+    }
+
+    #[test]
+    fn functions_and_bootstrap() {
+        let sys = "function Sys.init 0\npush constant 5\ncall Main.sum 1\npop static 0\nlabel END\ngoto END\n";
+        // sum(n) = n + (n - 1) + ... + 1, with a loop and a local
+        let main = "function Main.sum 1\n\
+                    label LOOP\npush argument 0\nif-goto BODY\npush local 0\nreturn\n\
+                    label BODY\npush local 0\npush argument 0\nadd\npop local 0\n\
+                    push argument 0\npush constant 1\nsub\npop argument 0\ngoto LOOP\n";
+        let emulator = run(&[("Sys.vm", sys), ("Main.vm", main)], 10000);
+        assert_eq!(emulator.peek(16), 15);
+        // the stack is back where Sys.init left it
+        assert_eq!(emulator.peek(0), 261);
+    }
+}
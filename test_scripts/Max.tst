@@ -0,0 +1,29 @@
+// Tests Max.asm: RAM[2] = max(RAM[0], RAM[1])
+
+load Max.asm,
+output-file Max.out,
+compare-to Max.cmp,
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+
+set RAM[0] 0,   // max(0, 0)
+set RAM[1] 0;
+repeat 14 {
+  ticktock;
+}
+output;
+
+set PC 0,
+set RAM[0] 1,   // max(1, 0)
+set RAM[1] 0;
+repeat 14 {
+  ticktock;
+}
+output;
+
+set PC 0,
+set RAM[0] 1234,   // max(1234, 23456)
+set RAM[1] 23456;
+repeat 14 {
+  ticktock;
+}
+output;